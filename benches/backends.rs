@@ -0,0 +1,82 @@
+//! Compare the interpreter and the JIT on a few fixed programs, in ops per second.
+//!
+//! Throughput is counted in ops the interpreter executes after optimization.
+//! `tight_loop` spends all its time in a few small nested loops, so it mostly measures
+//! how fast the interpreter dispatches ops.
+
+use std::io;
+
+use brainphoque::{optimize, parse, Interpreter, Op, DEFAULT_TAPE_SIZE};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const PROGRAMS: [(&str, &str); 4] = [
+    ("busy_loop", include_str!("programs/busy_loop.bf")),
+    ("hello", include_str!("programs/hello.bf")),
+    ("pointer_walk", include_str!("programs/pointer_walk.bf")),
+    ("tight_loop", include_str!("programs/tight_loop.bf")),
+];
+
+fn compile(src: &str) -> Vec<Op> {
+    optimize(parse(src).unwrap())
+}
+
+fn backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("backends");
+
+    for (name, src) in PROGRAMS {
+        let stats = Interpreter::new(compile(src), DEFAULT_TAPE_SIZE, io::empty(), io::sink())
+            .run()
+            .unwrap();
+        group.throughput(Throughput::Elements(stats.ops_executed));
+
+        group.bench_with_input(BenchmarkId::new("interpreter", name), src, |b, src| {
+            b.iter(|| {
+                Interpreter::new(compile(src), DEFAULT_TAPE_SIZE, io::empty(), io::sink())
+                    .run()
+                    .unwrap()
+            })
+        });
+
+        #[cfg(all(
+            feature = "jit",
+            any(target_arch = "aarch64", target_arch = "x86_64"),
+            any(target_os = "linux", target_os = "macos")
+        ))]
+        group.bench_with_input(BenchmarkId::new("jit", name), src, |b, src| {
+            b.iter(|| jit::run(compile(src)))
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+mod jit {
+    use brainphoque::{JitCompiler, Op, DEFAULT_TAPE_SIZE};
+
+    /// Compile and run `ops` with stdout pointed at /dev/null, since compiled code
+    /// writes straight to the file descriptor.
+    pub fn run(ops: Vec<Op>) {
+        let mut tape = vec![0u8; DEFAULT_TAPE_SIZE];
+        let compiled = JitCompiler::new(ops).compile().unwrap();
+
+        unsafe {
+            let stdout = libc::dup(libc::STDOUT_FILENO);
+            let null = libc::open(c"/dev/null".as_ptr(), libc::O_WRONLY);
+            libc::dup2(null, libc::STDOUT_FILENO);
+
+            compiled.call(tape.as_mut_ptr(), tape.len());
+
+            libc::dup2(stdout, libc::STDOUT_FILENO);
+            libc::close(null);
+            libc::close(stdout);
+        }
+    }
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! AArch64 instruction selection shared by the JIT and [`emit_asm`](crate::emit_asm), so
+//! the assembly it prints is the code the JIT runs.
+
+/// How to add a constant to a register, as picked by [`add_immediate`].
+pub(crate) enum AddImmediate {
+    /// ADD/SUB immediates of 12 bits: one shifted left by 12, then one unshifted. At
+    /// least one of them is there.
+    Immediates {
+        shifted: Option<u32>,
+        unshifted: Option<u32>,
+    },
+    /// The magnitude, built in X11 by [`load_immediate`] and added as a register.
+    Register(u64),
+}
+
+/// The operands for `Xd = Xn ± magnitude`. Magnitudes below 2^24 take at most two
+/// immediates; larger ones go through X11.
+pub(crate) fn add_immediate(magnitude: usize) -> AddImmediate {
+    if magnitude >= 1 << 24 {
+        return AddImmediate::Register(magnitude as u64);
+    }
+
+    let (high, low) = ((magnitude >> 12) as u32, (magnitude & 0xFFF) as u32);
+    AddImmediate::Immediates {
+        shifted: (high != 0).then_some(high),
+        // Still needed for a zero delta when the registers differ
+        unshifted: (low != 0 || high == 0).then_some(low),
+    }
+}
+
+/// The halfwords `Xd = value` is built from, with the shift of each: a MOVZ for the
+/// first, and a MOVK for each other one that isn't zero.
+pub(crate) fn load_immediate(value: u64) -> impl Iterator<Item = (u32, u32)> {
+    (0..4).filter_map(move |shift| {
+        let halfword = (value >> (16 * shift) & 0xFFFF) as u32;
+        (shift == 0 || halfword != 0).then_some((halfword, 16 * shift))
+    })
+}
@@ -0,0 +1,289 @@
+//! A compact binary encoding of ops, so an optimized program can be saved and run again
+//! without parsing and optimizing it.
+//!
+//! The format is the 4 bytes `BFC\0`, a version byte, the number of ops as a
+//! little-endian `u32`, then each op as a tag byte followed by its operands. Deltas and
+//! factors are single bytes; offsets and counts are LEB128 varints, signed and unsigned.
+//! Offsets and counts are limited to [`MAX_OPERAND`], so a small file can't ask for more
+//! than the backends can run.
+
+use std::fmt;
+
+use crate::Op;
+
+/// First bytes of every bytecode file.
+const MAGIC: &[u8; 4] = b"BFC\0";
+
+/// Largest offset or count an op may hold, as the x86-64 JIT encodes them as 32-bit
+/// immediates. No parsed program comes near it.
+const MAX_OPERAND: u64 = i32::MAX as u64;
+
+/// Version of the format written by [`emit_bytecode`], and the only one
+/// [`load_bytecode`] reads.
+pub const BYTECODE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// The data doesn't start with the bytecode magic bytes.
+    NotBytecode,
+    /// The data was written in a version of the format this build can't read.
+    UnsupportedVersion { version: u8 },
+    /// The data ends in the middle of an op, or before the number of ops it promises.
+    Truncated,
+    /// A byte that is not an op tag, at byte offset `pos`.
+    UnknownOp { pos: usize, tag: u8 },
+    /// A varint operand larger than any backend runs, at byte offset `pos`.
+    OperandOutOfRange { pos: usize },
+    /// A `[` or `]` with no match, which no parsed program has.
+    UnbalancedJumps,
+    /// Bytes left over after the last op, at byte offset `pos`.
+    TrailingBytes { pos: usize },
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "not a bytecode file"),
+            BytecodeError::UnsupportedVersion { version } => write!(
+                f,
+                "bytecode version {} is not supported, only version {}",
+                version, BYTECODE_VERSION
+            ),
+            BytecodeError::Truncated => write!(f, "bytecode ends early"),
+            BytecodeError::UnknownOp { pos, tag } => {
+                write!(f, "unknown op 0x{:02X} at byte {}", tag, pos)
+            }
+            BytecodeError::OperandOutOfRange { pos } => {
+                write!(f, "operand out of range at byte {}", pos)
+            }
+            BytecodeError::UnbalancedJumps => write!(f, "bytecode has unmatched jumps"),
+            BytecodeError::TrailingBytes { pos } => {
+                write!(f, "unexpected bytes after the last op at byte {}", pos)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// Encode `ops` as bytecode, for [`load_bytecode`] to read back.
+///
+/// # Panics
+///
+/// Panics if `ops` holds an [`Op::Loop`]: only flat ops are encoded, so
+/// [`lower`](crate::lower) a tree first.
+pub fn emit_bytecode(ops: &[Op]) -> Vec<u8> {
+    let count = u32::try_from(ops.len()).expect("too many ops for bytecode");
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 5 + ops.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(BYTECODE_VERSION);
+    bytes.extend_from_slice(&count.to_le_bytes());
+
+    for op in ops {
+        match *op {
+            Op::Inc => bytes.push(0),
+            Op::Dec => bytes.push(1),
+            Op::MoveLeft => bytes.push(2),
+            Op::MoveRight => bytes.push(3),
+            Op::Output => bytes.push(4),
+            Op::Input => bytes.push(5),
+            Op::JumpIfZero => bytes.push(6),
+            Op::JumpIfNonZero => bytes.push(7),
+            Op::Add(delta) => bytes.extend_from_slice(&[8, delta as u8]),
+            Op::Move(delta) => {
+                bytes.push(9);
+                write_signed(&mut bytes, delta as i64);
+            }
+            Op::SetZero => bytes.push(10),
+            Op::AddMul { offset, factor } => {
+                bytes.push(11);
+                write_signed(&mut bytes, offset as i64);
+                bytes.push(factor as u8);
+            }
+            Op::OutputRepeat(count) => {
+                bytes.push(12);
+                write_unsigned(&mut bytes, count as u64);
+            }
+            Op::AddAtOffset { offset, delta } => {
+                bytes.push(13);
+                write_signed(&mut bytes, offset as i64);
+                bytes.push(delta as u8);
+            }
+            Op::ScanRight => bytes.push(14),
+            Op::ScanLeft => bytes.push(15),
+            Op::SetConst(value) => bytes.extend_from_slice(&[16, value as u8]),
+            Op::DebugDump => bytes.push(17),
+            Op::Loop(_) => panic!("loops must be lowered before they are encoded"),
+        }
+    }
+
+    bytes
+}
+
+/// Decode bytecode written by [`emit_bytecode`] back into ops, ready to run.
+///
+/// The ops are checked to have balanced jumps, so a corrupted file is an error rather
+/// than a panic when it runs.
+pub fn load_bytecode(bytes: &[u8]) -> Result<Vec<Op>, BytecodeError> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    if reader.take(MAGIC.len()) != Some(MAGIC) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    match reader.byte()? {
+        BYTECODE_VERSION => {}
+        version => return Err(BytecodeError::UnsupportedVersion { version }),
+    }
+    let count = reader.take(4).ok_or(BytecodeError::Truncated)?;
+    let count = u32::from_le_bytes(count.try_into().unwrap());
+
+    // Every op takes at least a byte, so a bogus count can't allocate more than the data
+    let mut ops = Vec::with_capacity((count as usize).min(bytes.len()));
+    for _ in 0..count {
+        let pos = reader.pos;
+        let op = match reader.byte()? {
+            0 => Op::Inc,
+            1 => Op::Dec,
+            2 => Op::MoveLeft,
+            3 => Op::MoveRight,
+            4 => Op::Output,
+            5 => Op::Input,
+            6 => Op::JumpIfZero,
+            7 => Op::JumpIfNonZero,
+            8 => Op::Add(reader.byte()? as i8),
+            9 => Op::Move(reader.signed()?),
+            10 => Op::SetZero,
+            11 => Op::AddMul {
+                offset: reader.signed()?,
+                factor: reader.byte()? as i8,
+            },
+            12 => Op::OutputRepeat(reader.unsigned()?),
+            13 => Op::AddAtOffset {
+                offset: reader.signed()?,
+                delta: reader.byte()? as i8,
+            },
+            14 => Op::ScanRight,
+            15 => Op::ScanLeft,
+            16 => Op::SetConst(reader.byte()? as i8),
+            17 => Op::DebugDump,
+            tag => return Err(BytecodeError::UnknownOp { pos, tag }),
+        };
+        ops.push(op);
+    }
+
+    if reader.pos < bytes.len() {
+        return Err(BytecodeError::TrailingBytes { pos: reader.pos });
+    }
+    if !balanced(&ops) {
+        return Err(BytecodeError::UnbalancedJumps);
+    }
+
+    Ok(ops)
+}
+
+/// Whether every `[` in `ops` has a matching `]`, as the backends require.
+fn balanced(ops: &[Op]) -> bool {
+    let mut depth = 0usize;
+    for op in ops {
+        match op {
+            Op::JumpIfZero => depth += 1,
+            Op::JumpIfNonZero => match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return false,
+            },
+            _ => {}
+        }
+    }
+
+    depth == 0
+}
+
+/// Append `value` as an unsigned LEB128 varint.
+fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Append `value` as a signed LEB128 varint.
+fn write_signed(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        // Done once the rest is all sign bits, and the sign bit of this byte agrees
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Reads bytecode a field at a time, failing with [`BytecodeError::Truncated`] at the end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    /// Offset of the next byte to read
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let taken = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        self.take(1)
+            .map(|byte| byte[0])
+            .ok_or(BytecodeError::Truncated)
+    }
+
+    /// Read the bits of a LEB128 varint, returning them with how many there are.
+    fn varint(&mut self) -> Result<(u64, u32), BytecodeError> {
+        let pos = self.pos;
+        let mut value = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            if shift >= u64::BITS {
+                return Err(BytecodeError::OperandOutOfRange { pos });
+            }
+            value |= u64::from(byte & 0x7F) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok((value, shift));
+            }
+        }
+    }
+
+    fn unsigned(&mut self) -> Result<usize, BytecodeError> {
+        let pos = self.pos;
+        let (value, _) = self.varint()?;
+        if value > MAX_OPERAND {
+            return Err(BytecodeError::OperandOutOfRange { pos });
+        }
+        Ok(value as usize)
+    }
+
+    fn signed(&mut self) -> Result<isize, BytecodeError> {
+        let pos = self.pos;
+        let (mut value, bits) = self.varint()?;
+        // Extend the sign bit of the last byte
+        if bits < u64::BITS && value & (1 << (bits - 1)) != 0 {
+            value |= !0 << bits;
+        }
+        let value = value as i64;
+        if value.unsigned_abs() > MAX_OPERAND {
+            return Err(BytecodeError::OperandOutOfRange { pos });
+        }
+        Ok(value as isize)
+    }
+}
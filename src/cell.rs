@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// A tape cell. Arithmetic wraps around at the width of the cell.
+pub trait Cell: Copy + PartialEq + fmt::Display {
+    const ZERO: Self;
+    const ONE: Self;
+    /// All bits set, i.e. -1 in two's complement.
+    const MAX: Self;
+
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    /// Zero-extend a byte read by `,` into a cell.
+    fn from_byte(byte: u8) -> Self;
+    /// The low byte of the cell, which is what `.` writes, as in most wide-cell
+    /// interpreters.
+    fn to_byte(self) -> u8;
+
+    /// Index of the first zero cell in `cells`.
+    fn find_zero(cells: &[Self]) -> Option<usize> {
+        cells.iter().position(|&cell| cell == Self::ZERO)
+    }
+
+    /// Index of the last zero cell in `cells`.
+    fn rfind_zero(cells: &[Self]) -> Option<usize> {
+        cells.iter().rposition(|&cell| cell == Self::ZERO)
+    }
+}
+
+/// Implement [`Cell`] for unsigned integer types, each followed by a block of any methods
+/// it overrides.
+macro_rules! impl_cell {
+    ($($ty:ty { $($overrides:item)* }),*) => {
+        $(
+            impl Cell for $ty {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                const MAX: Self = <$ty>::MAX;
+
+                fn wrapping_add(self, rhs: Self) -> Self {
+                    <$ty>::wrapping_add(self, rhs)
+                }
+
+                fn wrapping_sub(self, rhs: Self) -> Self {
+                    <$ty>::wrapping_sub(self, rhs)
+                }
+
+                fn wrapping_mul(self, rhs: Self) -> Self {
+                    <$ty>::wrapping_mul(self, rhs)
+                }
+
+                fn from_byte(byte: u8) -> Self {
+                    byte as Self
+                }
+
+                fn to_byte(self) -> u8 {
+                    self as u8
+                }
+
+                $($overrides)*
+            }
+        )*
+    };
+}
+
+impl_cell!(u8 {
+    // Byte tapes can be searched with SIMD
+    fn find_zero(cells: &[Self]) -> Option<usize> {
+        memchr::memchr(0, cells)
+    }
+
+    fn rfind_zero(cells: &[Self]) -> Option<usize> {
+        memchr::memrchr(0, cells)
+    }
+});
+impl_cell!(u16 {}, u32 {});
@@ -0,0 +1,119 @@
+use std::ops::Range;
+
+use crate::Op;
+
+/// Code sequences for one compilation target, such as a JIT architecture, a source
+/// language or the interpreter's table of handlers.
+///
+/// Each method appends the code for a single op; loop bookkeeping and branch
+/// patching are left to the implementation. [`codegen`] is the only place that maps ops
+/// onto these methods, so the interpreter, every JIT architecture and every emitted
+/// language handle the same set of ops.
+pub(crate) trait CodeGen {
+    /// The finished program, such as machine code or source text.
+    type Output;
+
+    fn inc(&mut self);
+    fn dec(&mut self);
+    fn move_left(&mut self);
+    fn move_right(&mut self);
+    fn output(&mut self);
+    /// Output the current cell `count` times. Backends that can write the run in one go
+    /// override this.
+    fn output_repeat(&mut self, count: usize) {
+        for _ in 0..count {
+            self.output();
+        }
+    }
+    /// Read a byte into the current cell. Compiled code stores 0 at end of input, the
+    /// interpreter's default [`EofPolicy`](crate::EofPolicy); the interpreter itself
+    /// follows the policy it was given.
+    fn input(&mut self);
+    /// Report the tape for a `#` extension command. Backends skip it by default.
+    fn debug_dump(&mut self) {}
+    /// Add `delta` to the current cell, wrapping at 8 bits.
+    fn add(&mut self, delta: i8);
+    fn move_pointer(&mut self, delta: isize);
+    fn set_zero(&mut self);
+    /// Set the current cell to `value`, wrapping at 8 bits. Backends that can store a
+    /// constant directly override this.
+    fn set_const(&mut self, value: i8) {
+        self.set_zero();
+        self.add(value);
+    }
+    /// Add the current cell times `factor` to the cell `offset` away.
+    fn add_mul(&mut self, offset: isize, factor: i8);
+    /// Add `delta` to the cell `offset` away, wrapping at 8 bits.
+    fn add_at_offset(&mut self, offset: isize, delta: i8);
+    fn loop_start(&mut self);
+    fn loop_end(&mut self);
+    /// Skip the code up to the matching [`end_if`](CodeGen::end_if) when the current cell
+    /// is zero.
+    fn if_nonzero(&mut self);
+    fn end_if(&mut self);
+    /// Move `step` cells at a time until the current cell is zero. Backends without a
+    /// faster search emit it as the original loop.
+    fn scan(&mut self, step: isize) {
+        self.loop_start();
+        self.move_pointer(step);
+        self.loop_end();
+    }
+    /// Amount of code generated so far, such as a number of bytes.
+    fn len(&self) -> usize;
+    /// Append the epilogue and return the finished code.
+    fn finish(self) -> Self::Output;
+}
+
+/// Generate code for `ops` with the given backend.
+pub(crate) fn codegen<G: CodeGen>(gen: G, ops: &[Op]) -> G::Output {
+    codegen_with_ranges(gen, ops).0
+}
+
+/// Generate code for `ops`, also returning the range of code generated for each op, as
+/// measured by [`CodeGen::len`].
+///
+/// Code after the last range belongs to the epilogue.
+pub(crate) fn codegen_with_ranges<G: CodeGen>(
+    mut gen: G,
+    ops: &[Op],
+) -> (G::Output, Vec<Range<usize>>) {
+    let mut ranges = Vec::with_capacity(ops.len());
+
+    for (ip, op) in ops.iter().enumerate() {
+        let start = gen.len();
+        match op {
+            Op::Inc => gen.inc(),
+            Op::Dec => gen.dec(),
+            Op::MoveLeft => gen.move_left(),
+            Op::MoveRight => gen.move_right(),
+            Op::Output => gen.output(),
+            Op::Input => gen.input(),
+            Op::JumpIfZero => gen.loop_start(),
+            Op::JumpIfNonZero => gen.loop_end(),
+            Op::Add(delta) => gen.add(*delta),
+            Op::Move(delta) => gen.move_pointer(*delta),
+            Op::SetZero => gen.set_zero(),
+            Op::AddMul { offset, factor } => {
+                // The loop a group of multiplies came from never ran its body on a zero
+                // cell, so the group mustn't touch the cells it targets either
+                if ip == 0 || !matches!(ops[ip - 1], Op::AddMul { .. }) {
+                    gen.if_nonzero();
+                }
+                gen.add_mul(*offset, *factor);
+                if !matches!(ops.get(ip + 1), Some(Op::AddMul { .. })) {
+                    gen.end_if();
+                }
+            }
+            Op::OutputRepeat(count) => gen.output_repeat(*count),
+            Op::AddAtOffset { offset, delta } => gen.add_at_offset(*offset, *delta),
+            Op::ScanRight => gen.scan(1),
+            Op::ScanLeft => gen.scan(-1),
+            Op::SetConst(value) => gen.set_const(*value),
+            Op::DebugDump => gen.debug_dump(),
+            Op::Loop(_) => unreachable!("loops are lowered before code generation"),
+        }
+        ranges.push(start..gen.len());
+    }
+
+    (gen.finish(), ranges)
+}
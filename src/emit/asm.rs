@@ -0,0 +1,274 @@
+use std::fmt::Write;
+
+use crate::{
+    aarch64::{self, AddImmediate},
+    codegen::CodeGen,
+};
+
+/// Largest run of output written from one stack buffer, as in the JIT.
+const OUTPUT_BATCH: usize = 2048;
+
+/// AArch64 GAS assembly code generator for Linux, mirroring the JIT's instructions.
+///
+/// The program is a `main` function with the tape in `.bss`. As in the JIT, the data
+/// pointer lives in X0, the start of the tape in X9, and the first cell is returned as
+/// the exit status, or -errno once a `read` or `write` fails. Branches name their
+/// targets and leave the displacement to the assembler, so they take a single
+/// instruction where the JIT pads them for far targets.
+pub struct Asm {
+    code: String,
+    tape_size: usize,
+    /// Number of loops and ifs opened so far, used to name their labels
+    labels: usize,
+    /// Label numbers of each `[` or if still waiting for its end
+    loops: Vec<usize>,
+    /// Whether any `read` or `write` branches to the failure exit
+    io_checks: bool,
+}
+
+impl Asm {
+    pub fn new(tape_size: usize) -> Self {
+        let mut code = String::new();
+        code.push_str("    .text\n");
+        code.push_str("    .globl main\n");
+        code.push_str("    .type main, %function\n");
+        code.push_str("    .p2align 2\n");
+        code.push_str("main:\n");
+
+        let mut asm = Self {
+            code,
+            tape_size,
+            labels: 0,
+            loops: vec![],
+            io_checks: false,
+        };
+        asm.line("adrp x0, tape");
+        asm.line("add x0, x0, :lo12:tape");
+        asm.line("mov x9, x0");
+        asm
+    }
+
+    fn line(&mut self, instruction: &str) {
+        self.code.push_str("    ");
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    fn label(&mut self, name: &str) {
+        self.code.push_str(name);
+        self.code.push_str(":\n");
+    }
+
+    /// Emit `Xd = Xn + delta` with the instructions [`aarch64::add_immediate`] picks.
+    fn add_immediate(&mut self, rd: u32, rn: u32, delta: isize) {
+        let mnemonic = if delta >= 0 { "add" } else { "sub" };
+
+        match aarch64::add_immediate(delta.unsigned_abs()) {
+            AddImmediate::Register(magnitude) => {
+                for (halfword, shift) in aarch64::load_immediate(magnitude) {
+                    if shift == 0 {
+                        self.line(&format!("movz x11, #{}", halfword));
+                    } else {
+                        self.line(&format!("movk x11, #{}, lsl #{}", halfword, shift));
+                    }
+                }
+                self.line(&format!("{} x{}, x{}, x11", mnemonic, rd, rn));
+            }
+            AddImmediate::Immediates { shifted, unshifted } => {
+                let mut rn = rn;
+                if let Some(high) = shifted {
+                    self.line(&format!(
+                        "{} x{}, x{}, #{}, lsl #12",
+                        mnemonic, rd, rn, high
+                    ));
+                    rn = rd;
+                }
+                if let Some(low) = unshifted {
+                    self.line(&format!("{} x{}, x{}, #{}", mnemonic, rd, rn, low));
+                }
+            }
+        }
+    }
+
+    /// Write `length` bytes from `buffer` to stdout, leaving the result in X4.
+    fn write(&mut self, buffer: &str, length: usize) {
+        self.line("mov x0, #1");
+        self.line(&format!("mov x1, {}", buffer));
+        self.line(&format!("mov x2, #{}", length));
+        self.line("mov x8, #64");
+        self.line("svc #0");
+        self.line("mov x4, x0");
+    }
+
+    /// Branch to the I/O failure exit if the syscall whose result is in X4 failed.
+    fn check_io(&mut self) {
+        self.io_checks = true;
+        self.line("cmp x4, #0");
+        self.line("b.lt .Lio_failed");
+    }
+}
+
+impl CodeGen for Asm {
+    type Output = Vec<u8>;
+
+    fn inc(&mut self) {
+        self.add(1);
+    }
+
+    fn dec(&mut self) {
+        self.add(-1);
+    }
+
+    fn move_left(&mut self) {
+        self.line("sub x0, x0, #1");
+    }
+
+    fn move_right(&mut self) {
+        self.line("add x0, x0, #1");
+    }
+
+    fn output(&mut self) {
+        self.line("mov x3, x0");
+        self.write("x3", 1);
+        self.line("mov x0, x3");
+        self.check_io();
+    }
+
+    fn output_repeat(&mut self, count: usize) {
+        let mut remaining = count;
+        while remaining > 0 {
+            let batch = remaining.min(OUTPUT_BATCH);
+            // SP must stay 16-byte aligned
+            let reserved = batch.next_multiple_of(16);
+
+            // Fill a stack buffer with copies of the cell and write it in one syscall
+            self.line("mov x3, x0");
+            self.line("ldrb w1, [x0]");
+            self.line(&format!("sub sp, sp, #{}", reserved));
+            self.line(&format!("mov x5, #{}", batch));
+            self.label("1");
+            self.line("sub x5, x5, #1");
+            self.line("strb w1, [sp, x5]");
+            self.line("cbnz x5, 1b");
+            self.write("sp", batch);
+            self.line(&format!("add sp, sp, #{}", reserved));
+            self.line("mov x0, x3");
+            self.check_io();
+
+            remaining -= batch;
+        }
+    }
+
+    fn input(&mut self) {
+        self.line("mov x3, x0");
+        // A read interrupted by a signal before it got any input is issued again
+        self.label("2");
+        self.line("mov x0, #0");
+        self.line("mov x1, x3");
+        self.line("mov x2, #1");
+        self.line("mov x8, #63");
+        self.line("svc #0");
+        self.line("cmn x0, #4");
+        self.line("b.eq 2b");
+        self.line("mov x4, x0");
+        self.check_io();
+        self.line("cbnz x0, 1f");
+        self.line("strb wzr, [x3]");
+        self.label("1");
+        self.line("mov x0, x3");
+    }
+
+    fn add(&mut self, delta: i8) {
+        let mnemonic = if delta >= 0 { "add" } else { "sub" };
+        self.line("ldrb w1, [x0]");
+        self.line(&format!("{} w1, w1, #{}", mnemonic, delta.unsigned_abs()));
+        self.line("strb w1, [x0]");
+    }
+
+    fn move_pointer(&mut self, delta: isize) {
+        self.add_immediate(0, 0, delta);
+    }
+
+    fn set_zero(&mut self) {
+        self.line("strb wzr, [x0]");
+    }
+
+    fn set_const(&mut self, value: i8) {
+        self.line(&format!("mov w1, #{}", value as u8));
+        self.line("strb w1, [x0]");
+    }
+
+    fn add_mul(&mut self, offset: isize, factor: i8) {
+        let mnemonic = if factor >= 0 { "add" } else { "sub" };
+        self.line("ldrb w1, [x0]");
+        self.line(&format!("mov w2, #{}", factor.unsigned_abs()));
+        self.line("mul w2, w1, w2");
+        self.add_immediate(4, 0, offset);
+        self.line("ldrb w3, [x4]");
+        self.line(&format!("{} w3, w3, w2", mnemonic));
+        self.line("strb w3, [x4]");
+    }
+
+    fn add_at_offset(&mut self, offset: isize, delta: i8) {
+        let mnemonic = if delta >= 0 { "add" } else { "sub" };
+        self.add_immediate(4, 0, offset);
+        self.line("ldrb w3, [x4]");
+        self.line(&format!("{} w3, w3, #{}", mnemonic, delta.unsigned_abs()));
+        self.line("strb w3, [x4]");
+    }
+
+    fn loop_start(&mut self) {
+        let label = self.labels;
+        self.labels += 1;
+        self.loops.push(label);
+
+        self.line("ldrb w1, [x0]");
+        self.line(&format!("cbz w1, .Lloop{}_end", label));
+        self.label(&format!(".Lloop{}_body", label));
+    }
+
+    fn loop_end(&mut self) {
+        let label = self.loops.pop().expect("unbalanced loop");
+
+        self.line("ldrb w1, [x0]");
+        self.line(&format!("cbnz w1, .Lloop{}_body", label));
+        self.label(&format!(".Lloop{}_end", label));
+    }
+
+    fn if_nonzero(&mut self) {
+        let label = self.labels;
+        self.labels += 1;
+        self.loops.push(label);
+
+        self.line("ldrb w1, [x0]");
+        self.line(&format!("cbz w1, .Lif{}_end", label));
+    }
+
+    fn end_if(&mut self) {
+        let label = self.loops.pop().expect("unbalanced if");
+        self.label(&format!(".Lif{}_end", label));
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        // Return the first cell
+        self.line("ldrb w0, [x9]");
+        self.line("ret");
+        if self.io_checks {
+            // X4 holds -errno
+            self.label(".Lio_failed");
+            self.line("mov w0, w4");
+            self.line("ret");
+        }
+        self.line(".size main, .-main");
+        self.code.push('\n');
+        self.line(".bss");
+        self.line(".p2align 4");
+        self.label("tape");
+        writeln!(self.code, "    .zero {}", self.tape_size).unwrap();
+        self.code.into_bytes()
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+use crate::codegen::CodeGen;
+
+/// C code generator. The tape is a global `unsigned char` array so cells wrap at 8 bits,
+/// and `p` is the data pointer.
+pub struct C {
+    code: String,
+    /// Nesting depth of the statement being emitted, used for indentation
+    depth: usize,
+}
+
+impl C {
+    pub fn new(tape_size: usize) -> Self {
+        let mut code = String::new();
+        code.push_str("#include <stdio.h>\n\n");
+        writeln!(code, "static unsigned char tape[{}];\n", tape_size).unwrap();
+        code.push_str("int main(void) {\n");
+        code.push_str("    unsigned char *p = tape;\n");
+
+        Self { code, depth: 1 }
+    }
+
+    fn line(&mut self, statement: &str) {
+        for _ in 0..self.depth {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(statement);
+        self.code.push('\n');
+    }
+}
+
+impl CodeGen for C {
+    type Output = Vec<u8>;
+
+    fn inc(&mut self) {
+        self.line("++*p;");
+    }
+
+    fn dec(&mut self) {
+        self.line("--*p;");
+    }
+
+    fn move_left(&mut self) {
+        self.line("--p;");
+    }
+
+    fn move_right(&mut self) {
+        self.line("++p;");
+    }
+
+    fn output(&mut self) {
+        self.line("putchar(*p);");
+    }
+
+    fn output_repeat(&mut self, count: usize) {
+        self.line(&format!("for (int i = 0; i < {}; ++i) putchar(*p);", count));
+    }
+
+    fn input(&mut self) {
+        self.line("{ int c = getchar(); *p = c == EOF ? 0 : c; }");
+    }
+
+    fn add(&mut self, delta: i8) {
+        if delta >= 0 {
+            self.line(&format!("*p += {};", delta));
+        } else {
+            self.line(&format!("*p -= {};", delta.unsigned_abs()));
+        }
+    }
+
+    fn move_pointer(&mut self, delta: isize) {
+        if delta >= 0 {
+            self.line(&format!("p += {};", delta));
+        } else {
+            self.line(&format!("p -= {};", delta.unsigned_abs()));
+        }
+    }
+
+    fn set_zero(&mut self) {
+        self.line("*p = 0;");
+    }
+
+    fn set_const(&mut self, value: i8) {
+        self.line(&format!("*p = {};", value as u8));
+    }
+
+    fn add_mul(&mut self, offset: isize, factor: i8) {
+        self.line(&format!("p[{}] += *p * {};", offset, factor));
+    }
+
+    fn add_at_offset(&mut self, offset: isize, delta: i8) {
+        self.line(&format!("p[{}] += {};", offset, delta));
+    }
+
+    fn loop_start(&mut self) {
+        self.line("while (*p) {");
+        self.depth += 1;
+    }
+
+    fn loop_end(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn if_nonzero(&mut self) {
+        self.line("if (*p) {");
+        self.depth += 1;
+    }
+
+    fn end_if(&mut self) {
+        self.loop_end();
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.line("return 0;");
+        self.code.push_str("}\n");
+        self.code.into_bytes()
+    }
+}
@@ -0,0 +1,33 @@
+//! Translate ops into source code for other languages.
+
+use crate::{codegen::codegen, Op};
+
+mod asm;
+mod c;
+mod rust;
+mod wasm;
+
+/// Translate `ops` into a standalone C program with a tape of `tape_size` cells.
+pub fn emit_c(ops: &[Op], tape_size: usize) -> String {
+    String::from_utf8(codegen(c::C::new(tape_size), ops)).unwrap()
+}
+
+/// Translate `ops` into a standalone Rust program with a tape of `tape_size` cells.
+pub fn emit_rust(ops: &[Op], tape_size: usize) -> String {
+    String::from_utf8(codegen(rust::Rust::new(tape_size), ops)).unwrap()
+}
+
+/// Translate `ops` into a WebAssembly module in text format with a tape of at least
+/// `tape_size` cells.
+pub fn emit_wasm(ops: &[Op], tape_size: usize) -> String {
+    String::from_utf8(codegen(wasm::Wasm::new(tape_size), ops)).unwrap()
+}
+
+/// Translate `ops` into AArch64 assembly for Linux in GAS syntax, defining a `main` that
+/// runs the program on a tape of `tape_size` cells in `.bss`.
+///
+/// The instructions are the ones the JIT generates, without bounds checks. Like the JIT,
+/// the program exits with the value of the first cell.
+pub fn emit_asm(ops: &[Op], tape_size: usize) -> String {
+    String::from_utf8(codegen(asm::Asm::new(tape_size), ops)).unwrap()
+}
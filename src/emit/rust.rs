@@ -0,0 +1,153 @@
+use crate::codegen::CodeGen;
+
+/// Rust code generator. The tape is a `Vec<u8>` so cells wrap at 8 bits through
+/// `wrapping_add`/`wrapping_sub`, and `p` is the data pointer.
+///
+/// Indexing panics if the program leaves the tape, rather than corrupting memory.
+pub struct Rust {
+    code: String,
+    /// Nesting depth of the statement being emitted, used for indentation
+    depth: usize,
+}
+
+impl Rust {
+    pub fn new(tape_size: usize) -> Self {
+        let mut code = String::new();
+        // Programs without input or output leave some of these unused
+        code.push_str("#![allow(unused)]\n\n");
+        code.push_str("use std::io::{self, Read, Write};\n\n");
+        code.push_str("fn main() {\n");
+
+        let mut rust = Self { code, depth: 1 };
+        rust.line(&format!("let mut tape = vec![0u8; {}];", tape_size));
+        rust.line("let mut p: usize = 0;");
+        rust.line("let mut input = io::stdin().lock().bytes();");
+        rust.line("let mut output = io::BufWriter::new(io::stdout().lock());");
+        rust
+    }
+
+    fn line(&mut self, statement: &str) {
+        for _ in 0..self.depth {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(statement);
+        self.code.push('\n');
+    }
+
+    /// Index expression for the cell `offset` away from the current one.
+    fn cell(offset: isize) -> String {
+        match offset {
+            0 => "tape[p]".to_string(),
+            offset if offset > 0 => format!("tape[p + {}]", offset),
+            offset => format!("tape[p - {}]", offset.unsigned_abs()),
+        }
+    }
+
+    /// Add `value`, an expression of type `u8`, to `cell`, subtracting instead if
+    /// `negative`.
+    fn wrapping_add(&mut self, cell: &str, value: &str, negative: bool) {
+        let method = if negative {
+            "wrapping_sub"
+        } else {
+            "wrapping_add"
+        };
+        self.line(&format!("{} = {}.{}({});", cell, cell, method, value));
+    }
+}
+
+impl CodeGen for Rust {
+    type Output = Vec<u8>;
+
+    fn inc(&mut self) {
+        self.add(1);
+    }
+
+    fn dec(&mut self) {
+        self.add(-1);
+    }
+
+    fn move_left(&mut self) {
+        self.move_pointer(-1);
+    }
+
+    fn move_right(&mut self) {
+        self.move_pointer(1);
+    }
+
+    fn output(&mut self) {
+        self.line("output.write_all(&[tape[p]]).unwrap();");
+    }
+
+    fn output_repeat(&mut self, count: usize) {
+        self.line(&format!(
+            "output.write_all(&vec![tape[p]; {}]).unwrap();",
+            count
+        ));
+    }
+
+    fn input(&mut self) {
+        // Make any prompt visible before blocking on input
+        self.line("output.flush().unwrap();");
+        self.line("tape[p] = input.next().map_or(0, |byte| byte.unwrap());");
+    }
+
+    fn add(&mut self, delta: i8) {
+        let value = delta.unsigned_abs().to_string();
+        self.wrapping_add("tape[p]", &value, delta < 0);
+    }
+
+    fn move_pointer(&mut self, delta: isize) {
+        if delta >= 0 {
+            self.line(&format!("p += {};", delta));
+        } else {
+            self.line(&format!("p -= {};", delta.unsigned_abs()));
+        }
+    }
+
+    fn set_zero(&mut self) {
+        self.line("tape[p] = 0;");
+    }
+
+    fn set_const(&mut self, value: i8) {
+        self.line(&format!("tape[p] = {};", value as u8));
+    }
+
+    fn add_mul(&mut self, offset: isize, factor: i8) {
+        let value = format!("tape[p].wrapping_mul({})", factor.unsigned_abs());
+        self.wrapping_add(&Self::cell(offset), &value, factor < 0);
+    }
+
+    fn add_at_offset(&mut self, offset: isize, delta: i8) {
+        let value = delta.unsigned_abs().to_string();
+        self.wrapping_add(&Self::cell(offset), &value, delta < 0);
+    }
+
+    fn loop_start(&mut self) {
+        self.line("while tape[p] != 0 {");
+        self.depth += 1;
+    }
+
+    fn loop_end(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn if_nonzero(&mut self) {
+        self.line("if tape[p] != 0 {");
+        self.depth += 1;
+    }
+
+    fn end_if(&mut self) {
+        self.loop_end();
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.line("output.flush().unwrap();");
+        self.code.push_str("}\n");
+        self.code.into_bytes()
+    }
+}
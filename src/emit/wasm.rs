@@ -0,0 +1,155 @@
+use std::fmt::Write;
+
+use crate::codegen::CodeGen;
+
+/// WebAssembly text format code generator.
+///
+/// The module exports its linear memory as the tape and a `run` function. Output goes
+/// through an imported `env.write(byte)` and input through `env.read() -> i32`, which
+/// returns a negative value at end of input. The data pointer is the local `$p`.
+pub struct Wasm {
+    code: String,
+    /// Nesting depth of the instruction being emitted, used for indentation
+    depth: usize,
+}
+
+/// Size of a WebAssembly memory page in bytes.
+const PAGE_SIZE: usize = 65536;
+
+impl Wasm {
+    pub fn new(tape_size: usize) -> Self {
+        let mut code = String::new();
+        code.push_str("(module\n");
+        code.push_str("  (import \"env\" \"read\" (func $read (result i32)))\n");
+        code.push_str("  (import \"env\" \"write\" (func $write (param i32)))\n");
+        writeln!(
+            code,
+            "  (memory (export \"memory\") {})",
+            tape_size.div_ceil(PAGE_SIZE)
+        )
+        .unwrap();
+        code.push_str("  (func (export \"run\")\n");
+        code.push_str("    (local $p i32)\n");
+        code.push_str("    (local $c i32)\n");
+
+        Self { code, depth: 2 }
+    }
+
+    fn line(&mut self, instruction: &str) {
+        for _ in 0..self.depth {
+            self.code.push_str("  ");
+        }
+        self.code.push_str(instruction);
+        self.code.push('\n');
+    }
+
+    /// Store `value` into the current cell.
+    fn store(&mut self, value: &str) {
+        self.line(&format!("(i32.store8 (local.get $p) {})", value));
+    }
+}
+
+impl CodeGen for Wasm {
+    type Output = Vec<u8>;
+
+    fn inc(&mut self) {
+        self.add(1);
+    }
+
+    fn dec(&mut self) {
+        self.add(-1);
+    }
+
+    fn move_left(&mut self) {
+        self.move_pointer(-1);
+    }
+
+    fn move_right(&mut self) {
+        self.move_pointer(1);
+    }
+
+    fn output(&mut self) {
+        self.line("(call $write (i32.load8_u (local.get $p)))");
+    }
+
+    fn input(&mut self) {
+        self.line("(local.set $c (call $read))");
+        self.store("(select (i32.const 0) (local.get $c) (i32.lt_s (local.get $c) (i32.const 0)))");
+    }
+
+    fn add(&mut self, delta: i8) {
+        // Only the low byte is stored, so the sum wraps at 8 bits
+        self.store(&format!(
+            "(i32.add (i32.load8_u (local.get $p)) (i32.const {}))",
+            delta
+        ));
+    }
+
+    fn move_pointer(&mut self, delta: isize) {
+        self.line(&format!(
+            "(local.set $p (i32.add (local.get $p) (i32.const {})))",
+            delta
+        ));
+    }
+
+    fn set_zero(&mut self) {
+        self.store("(i32.const 0)");
+    }
+
+    fn set_const(&mut self, value: i8) {
+        self.store(&format!("(i32.const {})", value as u8));
+    }
+
+    fn add_mul(&mut self, offset: isize, factor: i8) {
+        let target = format!("(i32.add (local.get $p) (i32.const {}))", offset);
+        self.line(&format!(
+            "(i32.store8 {} (i32.add (i32.load8_u {}) (i32.mul (i32.load8_u (local.get $p)) (i32.const {}))))",
+            target, target, factor
+        ));
+    }
+
+    fn add_at_offset(&mut self, offset: isize, delta: i8) {
+        let target = format!("(i32.add (local.get $p) (i32.const {}))", offset);
+        self.line(&format!(
+            "(i32.store8 {} (i32.add (i32.load8_u {}) (i32.const {})))",
+            target, target, delta
+        ));
+    }
+
+    fn loop_start(&mut self) {
+        // Label 1 is the enclosing block (exit), label 0 the loop itself (repeat)
+        self.line("(block");
+        self.line("  (loop");
+        self.depth += 2;
+        self.line("(br_if 1 (i32.eqz (i32.load8_u (local.get $p))))");
+    }
+
+    fn loop_end(&mut self) {
+        self.line("(br 0)");
+        self.depth -= 2;
+        self.line("  )");
+        self.line(")");
+    }
+
+    fn if_nonzero(&mut self) {
+        self.line("(if (i32.load8_u (local.get $p))");
+        self.line("  (then");
+        self.depth += 2;
+    }
+
+    fn end_if(&mut self) {
+        self.depth -= 2;
+        self.line("  )");
+        self.line(")");
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.code.push_str("  )\n");
+        self.code.push_str(")\n");
+        self.code.into_bytes()
+    }
+}
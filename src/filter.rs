@@ -0,0 +1,193 @@
+//! Byte translations applied to a program's input and output, for programs written for
+//! another platform's line endings or that expect their input in one case.
+//!
+//! The filters wrap the streams the interpreter is given, so the interpreter itself never
+//! sees them.
+
+use std::{
+    io::{self, Read, Write},
+    mem,
+};
+
+/// One translation of a byte stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteFilter {
+    /// Replace each `\r\n` with `\n`, leaving a `\r` on its own alone.
+    CrlfToLf,
+    /// Replace each `\n` with `\r\n`.
+    LfToCrlf,
+    /// Uppercase ASCII letters.
+    Uppercase,
+    /// Lowercase ASCII letters.
+    Lowercase,
+}
+
+impl ByteFilter {
+    /// Translate `byte` onto the end of `out`. `held_cr` is whether the filter is holding
+    /// back a `\r` until it knows the byte after it.
+    fn apply(self, byte: u8, held_cr: &mut bool, out: &mut Vec<u8>) {
+        match self {
+            ByteFilter::CrlfToLf => {
+                if mem::take(held_cr) && byte != b'\n' {
+                    out.push(b'\r');
+                }
+                if byte == b'\r' {
+                    *held_cr = true;
+                } else {
+                    out.push(byte);
+                }
+            }
+            ByteFilter::LfToCrlf => {
+                if byte == b'\n' {
+                    out.push(b'\r');
+                }
+                out.push(byte);
+            }
+            ByteFilter::Uppercase => out.push(byte.to_ascii_uppercase()),
+            ByteFilter::Lowercase => out.push(byte.to_ascii_lowercase()),
+        }
+    }
+}
+
+/// Filters applied one after the other, with what each holds back between calls.
+#[derive(Debug)]
+struct Chain {
+    filters: Vec<ByteFilter>,
+    held_cr: Vec<bool>,
+}
+
+impl Chain {
+    fn new(filters: &[ByteFilter]) -> Self {
+        Self {
+            filters: filters.to_vec(),
+            held_cr: vec![false; filters.len()],
+        }
+    }
+
+    /// Translate the next `bytes` of the stream.
+    fn translate(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.run(bytes, false)
+    }
+
+    /// Translate whatever the filters still hold back at the end of the stream.
+    fn finish(&mut self) -> Vec<u8> {
+        self.run(&[], true)
+    }
+
+    fn run(&mut self, bytes: &[u8], end: bool) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        for (filter, held_cr) in self.filters.iter().zip(&mut self.held_cr) {
+            let mut translated = Vec::with_capacity(bytes.len());
+            for byte in bytes {
+                filter.apply(byte, held_cr, &mut translated);
+            }
+            // Nothing follows a held `\r` at the end, so it stands on its own
+            if end && mem::take(held_cr) {
+                translated.push(b'\r');
+            }
+            bytes = translated;
+        }
+
+        bytes
+    }
+}
+
+/// A reader that passes what it reads from `inner` through a list of filters, in order.
+#[derive(Debug)]
+pub struct FilterReader<R> {
+    inner: R,
+    chain: Chain,
+    /// Translated bytes, read up to `pos`
+    pending: Vec<u8>,
+    pos: usize,
+    /// Whether `inner` has reached its end
+    done: bool,
+}
+
+impl<R: Read> FilterReader<R> {
+    /// Read from `inner` through `filters`, the first applied first.
+    pub fn new(inner: R, filters: &[ByteFilter]) -> Self {
+        Self {
+            inner,
+            chain: Chain::new(filters),
+            pending: vec![],
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for FilterReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A read can translate to nothing, such as a lone `\r` waiting for its `\n`
+        while self.pos == self.pending.len() {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+
+            let mut chunk = [0; 1024];
+            let read = self.inner.read(&mut chunk)?;
+            self.pending = if read == 0 {
+                self.done = true;
+                self.chain.finish()
+            } else {
+                self.chain.translate(&chunk[..read])
+            };
+            self.pos = 0;
+        }
+
+        let len = buf.len().min(self.pending.len() - self.pos);
+        buf[..len].copy_from_slice(&self.pending[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// A writer that passes what is written through a list of filters, in order, before
+/// writing it to `inner`.
+///
+/// A `\r` held back by [`ByteFilter::CrlfToLf`] is written when the writer is dropped,
+/// ignoring any error, as [`BufWriter`](io::BufWriter) does with its buffer.
+#[derive(Debug)]
+pub struct FilterWriter<W: Write> {
+    inner: W,
+    chain: Chain,
+}
+
+impl<W: Write> FilterWriter<W> {
+    /// Write to `inner` through `filters`, the first applied first.
+    pub fn new(inner: W, filters: &[ByteFilter]) -> Self {
+        Self {
+            inner,
+            chain: Chain::new(filters),
+        }
+    }
+}
+
+impl<W: Write> Write for FilterWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.chain.filters.is_empty() {
+            return self.inner.write(buf);
+        }
+
+        let translated = self.chain.translate(buf);
+        self.inner.write_all(&translated)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for FilterWriter<W> {
+    fn drop(&mut self) {
+        let held = self.chain.finish();
+        if !held.is_empty() {
+            let _ = self
+                .inner
+                .write_all(&held)
+                .and_then(|()| self.inner.flush());
+        }
+    }
+}
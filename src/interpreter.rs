@@ -0,0 +1,1052 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt,
+    io::{self, BufWriter, Cursor, Read, Write},
+    str::{self, Utf8Error},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    codegen::{codegen, CodeGen},
+    op::jump_table,
+    optimize, parse, Cell, Op, ParseError, RunStats,
+};
+
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// The tape needed to grow past the number of cells it is allowed to hold.
+    TapeOverflow { dp: usize },
+    /// The op at `ip` moved the data pointer left of the first cell of a strict tape.
+    PointerUnderflow { ip: usize },
+    /// The program executed more ops than its step budget allows.
+    StepLimitExceeded { limit: u64 },
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// The program ran for longer than its time budget allows.
+    Timeout { timeout: Duration },
+    /// The program entered an empty loop, `[]`, on a nonzero cell, which it can never
+    /// leave.
+    InfiniteLoop,
+    /// The source given to [`run_to_string`] isn't a valid program.
+    Parse(ParseError),
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::TapeOverflow { dp } => {
+                write!(f, "tape overflow: no room to grow the tape at cell {}", dp)
+            }
+            ExecutionError::PointerUnderflow { .. } => {
+                write!(f, "pointer underflow: moved left of cell 0")
+            }
+            ExecutionError::StepLimitExceeded { limit } => {
+                write!(f, "step limit exceeded: executed {} ops", limit)
+            }
+            ExecutionError::Io(err) => write!(f, "I/O error: {}", err),
+            ExecutionError::Timeout { timeout } => {
+                write!(f, "timed out after {} ms", timeout.as_millis())
+            }
+            ExecutionError::InfiniteLoop => {
+                write!(f, "infinite loop: entered [] on a nonzero cell")
+            }
+            ExecutionError::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+impl From<io::Error> for ExecutionError {
+    fn from(err: io::Error) -> Self {
+        ExecutionError::Io(err)
+    }
+}
+
+impl From<ParseError> for ExecutionError {
+    fn from(err: ParseError) -> Self {
+        ExecutionError::Parse(err)
+    }
+}
+
+/// Parse, optimize and interpret `src` with `input` as its input, returning everything it
+/// printed. Output that isn't valid UTF-8 is converted lossily.
+pub fn run_to_string(src: &str, input: &str) -> Result<String, ExecutionError> {
+    let mut interpreter = Interpreter::with_input_bytes(optimize(parse(src)?), input.as_bytes());
+    interpreter.run()?;
+
+    Ok(interpreter.output_lossy().into_owned())
+}
+
+/// Number of ops executed between checks of the clock, which are far slower than most ops.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// Most copies of a cell a run of `.` writes at once.
+const OUTPUT_BATCH: usize = 8192;
+
+/// What `,` stores in the current cell once the input is exhausted.
+///
+/// The usual `cat` program `,[.,]` stops at the end of input only under [`Zero`]. Under
+/// [`Unchanged`] it would repeat the last byte forever, so clear the cell before reading
+/// with `,[.[-],]`; under [`NegativeOne`] test for -1 instead with `,+[-.,+]`. The JIT
+/// always stores 0.
+///
+/// [`Zero`]: EofPolicy::Zero
+/// [`Unchanged`]: EofPolicy::Unchanged
+/// [`NegativeOne`]: EofPolicy::NegativeOne
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EofPolicy {
+    /// Leave the cell as it was.
+    Unchanged,
+    /// Store 0. This is the default.
+    #[default]
+    Zero,
+    /// Store -1, i.e. 255 in an 8-bit cell or all bits set in wider cells.
+    NegativeOne,
+}
+
+/// How `.` writes a cell.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Write the cell's low byte. This is the default.
+    #[default]
+    Raw,
+    /// Write the cell's value in decimal followed by a space, so programs that compute
+    /// numbers can be read without converting them to digits themselves.
+    Decimal,
+}
+
+/// What happens when the data pointer moves past either end of the tape.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PointerMode {
+    /// Moving right of the last cell is a [`ExecutionError::TapeOverflow`], and moving
+    /// left of the first cell is a [`ExecutionError::PointerUnderflow`]. This is the
+    /// default.
+    #[default]
+    Strict,
+    /// The tape also grows when moving left of the first cell, up to the same total size.
+    Grow,
+    /// The tape is a ring of exactly `max_cells` cells: moving right of the last cell
+    /// lands on the first and moving left of the first lands on the last.
+    Wrap,
+}
+
+/// Where an [`Interpreter`] is in its program, as captured by
+/// [`snapshot`](Interpreter::snapshot).
+///
+/// With the `serde` feature it can be serialized, for example to checkpoint a long
+/// computation on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VmState<C = u8> {
+    /// Index of the next op to execute
+    pub ip: usize,
+    /// Index of the current cell
+    pub dp: usize,
+    /// The cells the tape has grown to
+    pub tape: Vec<C>,
+}
+
+/// One byte printed by `.`, as passed to [`Interpreter::on_output`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputEvent {
+    /// The byte printed: the low byte of the cell
+    pub byte: u8,
+    /// Index of the op that printed it
+    pub ip: usize,
+    /// Index of the cell it came from
+    pub dp: usize,
+}
+
+/// Executes the op at `ip` and returns the index of the next op to execute.
+type Handler<R, W, C> = fn(&mut Interpreter<R, W, C>, usize) -> Result<usize, ExecutionError>;
+
+/// Builds the interpreter's table of handlers, one for each op.
+///
+/// Handlers read their operands from the op they run, so most methods only pick the
+/// handler.
+struct Handlers<R, W: Write, C> {
+    handlers: Vec<Handler<R, W, C>>,
+    /// Index of each `[` still waiting for its `]`
+    loops: Vec<usize>,
+}
+
+impl<R, W, C> Handlers<R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    fn push(&mut self, handler: Handler<R, W, C>) {
+        self.handlers.push(handler);
+    }
+}
+
+impl<R, W, C> CodeGen for Handlers<R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    type Output = Vec<Handler<R, W, C>>;
+
+    fn inc(&mut self) {
+        self.push(Interpreter::inc);
+    }
+
+    fn dec(&mut self) {
+        self.push(Interpreter::dec);
+    }
+
+    fn move_left(&mut self) {
+        self.push(Interpreter::move_left_one);
+    }
+
+    fn move_right(&mut self) {
+        self.push(Interpreter::move_right_one);
+    }
+
+    fn output(&mut self) {
+        self.push(Interpreter::output_one);
+    }
+
+    fn output_repeat(&mut self, _count: usize) {
+        self.push(Interpreter::output_repeat);
+    }
+
+    fn input(&mut self) {
+        self.push(Interpreter::input);
+    }
+
+    fn debug_dump(&mut self) {
+        self.push(Interpreter::debug_dump);
+    }
+
+    fn add(&mut self, _delta: i8) {
+        self.push(Interpreter::add);
+    }
+
+    fn move_pointer(&mut self, _delta: isize) {
+        self.push(Interpreter::move_pointer);
+    }
+
+    fn set_zero(&mut self) {
+        self.push(Interpreter::set_zero);
+    }
+
+    fn set_const(&mut self, _value: i8) {
+        self.push(Interpreter::set_const);
+    }
+
+    fn add_mul(&mut self, _offset: isize, _factor: i8) {
+        self.push(Interpreter::add_mul_op);
+    }
+
+    fn add_at_offset(&mut self, _offset: isize, _delta: i8) {
+        self.push(Interpreter::add_at_offset_op);
+    }
+
+    fn loop_start(&mut self) {
+        self.loops.push(self.handlers.len());
+        self.push(Interpreter::jump_if_zero);
+    }
+
+    fn loop_end(&mut self) {
+        let start = self.loops.pop().expect("unbalanced loop");
+        // `[]` spins forever on a nonzero cell, so its `[` checks for that
+        if start == self.handlers.len() - 1 {
+            self.handlers[start] = Interpreter::empty_loop;
+        }
+        self.push(Interpreter::jump_if_non_zero);
+    }
+
+    // Each multiply checks for a zero cell itself, so a group needs no extra handler
+    fn if_nonzero(&mut self) {}
+
+    fn end_if(&mut self) {}
+
+    fn scan(&mut self, step: isize) {
+        if step > 0 {
+            self.push(Interpreter::scan_right_op);
+        } else {
+            self.push(Interpreter::scan_left_op);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    fn finish(self) -> Self::Output {
+        self.handlers
+    }
+}
+
+/// Called before each op with the instruction pointer, data pointer, op and tape.
+type Hook<C> = Box<dyn FnMut(usize, usize, &Op, &[C])>;
+
+/// Called with each byte `.` prints instead of writing it.
+type OutputHook = Box<dyn FnMut(OutputEvent)>;
+
+/// Executes ops one at a time on a zeroed tape of `C` cells.
+///
+/// The tape starts with a single cell and grows with zeroed cells as the data pointer
+/// moves right, up to `max_cells`. The [`PointerMode`] decides what happens past either
+/// end.
+pub struct Interpreter<R, W: Write, C = u8> {
+    ops: Vec<Op>,
+    /// The function that executes each op, so running an op is a single indirect call
+    /// rather than a `match` on the op
+    handlers: Vec<Handler<R, W, C>>,
+    /// Index of the op to continue at when each `[` or `]` jumps
+    jump_table: Vec<usize>,
+    cells: VecDeque<C>,
+    max_cells: usize,
+    pointer_mode: PointerMode,
+    eof_policy: EofPolicy,
+    output_mode: OutputMode,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    /// When the current `run` has to finish by, if it has a timeout
+    deadline: Option<Instant>,
+    trace: bool,
+    /// Maximum number of ops to trace, after which tracing stops
+    trace_limit: Option<u64>,
+    on_op: Option<Hook<C>>,
+    on_output: Option<OutputHook>,
+    /// Index of the next op to execute
+    ip: usize,
+    /// Index of the current cell
+    dp: usize,
+    steps: u64,
+    /// Number of times each op was executed
+    executions: Vec<u64>,
+    jumps_taken: u64,
+    reader: R,
+    /// Output is buffered and flushed before reading input and when `run` returns
+    writer: BufWriter<W>,
+}
+
+impl<R, W> Interpreter<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Create an interpreter with 8-bit cells whose tape may grow up to `max_cells` cells.
+    pub fn new(ops: Vec<Op>, max_cells: usize, reader: R, writer: W) -> Self {
+        Self::with_cell_type(ops, max_cells, reader, writer)
+    }
+}
+
+impl<'a> Interpreter<Cursor<&'a [u8]>, Vec<u8>> {
+    /// Create an interpreter with 8-bit cells and a tape of the default size that reads
+    /// `input` and collects its output in memory, for [`Interpreter::output`].
+    pub fn with_input_bytes(ops: Vec<Op>, input: &'a [u8]) -> Self {
+        Self::new(ops, crate::DEFAULT_TAPE_SIZE, Cursor::new(input), vec![])
+    }
+}
+
+impl<R, C> Interpreter<R, Vec<u8>, C>
+where
+    R: Read,
+    C: Cell,
+{
+    /// Everything the program has printed so far. Output is buffered until it's flushed,
+    /// which [`Interpreter::run`] always does before returning.
+    pub fn output(&self) -> &[u8] {
+        self.writer.get_ref()
+    }
+
+    /// [`Interpreter::output`] as text, or an error if it isn't valid UTF-8.
+    pub fn output_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(self.output())
+    }
+
+    /// [`Interpreter::output`] as text, with each invalid UTF-8 sequence replaced by
+    /// U+FFFD.
+    pub fn output_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.output())
+    }
+
+    /// Flush and return everything the program printed.
+    pub fn into_output(self) -> Vec<u8> {
+        self.writer
+            .into_inner()
+            .expect("writing to a Vec can't fail")
+    }
+}
+
+impl<R, W, C> Interpreter<R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    /// Create an interpreter with `C` cells whose tape may grow up to `max_cells` cells.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `[` and `]` in `ops` aren't balanced, which [`parse`](crate::parse)
+    /// guarantees they are.
+    pub fn with_cell_type(ops: Vec<Op>, max_cells: usize, reader: R, writer: W) -> Self {
+        let executions = vec![0; ops.len()];
+
+        Self {
+            handlers: Self::handlers(&ops),
+            jump_table: jump_table(&ops),
+            ops,
+            cells: VecDeque::from([C::ZERO]),
+            max_cells,
+            pointer_mode: PointerMode::default(),
+            eof_policy: EofPolicy::default(),
+            output_mode: OutputMode::default(),
+            max_steps: None,
+            timeout: None,
+            deadline: None,
+            trace: false,
+            trace_limit: None,
+            on_op: None,
+            on_output: None,
+            ip: 0,
+            dp: 0,
+            steps: 0,
+            executions,
+            jumps_taken: 0,
+            writer: BufWriter::new(writer),
+            reader,
+        }
+    }
+
+    /// Choose what happens when the data pointer moves past either end of the tape.
+    pub fn pointer_mode(mut self, pointer_mode: PointerMode) -> Self {
+        self.pointer_mode = pointer_mode;
+        self
+    }
+
+    /// Start the data pointer `offset` cells from the left end of the tape instead of on
+    /// the first cell, so a program can move left from where it starts. The tape starts
+    /// out with every cell up to the data pointer, and they count towards `max_cells`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` isn't less than `max_cells`.
+    pub fn start_offset(mut self, offset: usize) -> Self {
+        assert!(
+            offset < self.max_cells,
+            "start offset {} is past the end of a tape of {} cells",
+            offset,
+            self.max_cells
+        );
+        self.reach(offset);
+        self.dp = offset;
+        self
+    }
+
+    /// Choose what `,` stores at end of input.
+    pub fn eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
+
+    /// Choose how `.` writes a cell.
+    pub fn output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.output_mode = output_mode;
+        self
+    }
+
+    /// Stop with [`ExecutionError::StepLimitExceeded`] after executing `max_steps` ops.
+    pub fn max_steps(mut self, max_steps: Option<u64>) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Stop with [`ExecutionError::Timeout`] once [`Interpreter::run`] has taken longer
+    /// than `timeout`. The clock is only checked every so many ops, so a run may overshoot
+    /// slightly.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Log every executed op with the instruction pointer, data pointer and current cell
+    /// to stderr.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Stop tracing after `trace_limit` ops; the program keeps running.
+    pub fn trace_limit(mut self, trace_limit: Option<u64>) -> Self {
+        self.trace_limit = trace_limit;
+        self
+    }
+
+    /// Call `hook` before every op with the instruction pointer, data pointer, op and
+    /// every cell the tape has grown to, to build custom tracers and profilers on.
+    ///
+    /// A hook takes the interpreter off its fast path: every op then goes through
+    /// [`step`](Self::step) and an indirect call to the hook, and the tape is laid out
+    /// contiguously again whenever it has grown to the left. Expect programs to run
+    /// several times slower with a hook installed; without one nothing changes.
+    pub fn on_op(mut self, hook: impl FnMut(usize, usize, &Op, &[C]) + 'static) -> Self {
+        self.on_op = Some(Box::new(hook));
+        self
+    }
+
+    /// Send every byte `.` prints to `hook` as an [`OutputEvent`], along with the op and
+    /// cell it came from, instead of writing it, so a UI can show which command printed
+    /// what. Send the events on a channel from `hook` to consume them elsewhere.
+    ///
+    /// Events always carry the cell's low byte, whatever the [`OutputMode`]. Without a
+    /// hook, output is written as usual.
+    pub fn on_output(mut self, hook: impl FnMut(OutputEvent) + 'static) -> Self {
+        self.on_output = Some(Box::new(hook));
+        self
+    }
+
+    /// Run the program to completion, returning statistics about the run.
+    pub fn run(&mut self) -> Result<RunStats, ExecutionError> {
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let result = self.execute();
+        // Flush whatever was written even if the program failed
+        let flushed = self.writer.flush();
+        let stats = result?;
+        flushed?;
+        Ok(stats)
+    }
+
+    fn execute(&mut self) -> Result<RunStats, ExecutionError> {
+        // Without limits, tracing or a hook there's nothing to check between ops, and
+        // `step` has nothing left to do after `dispatch`
+        if self.max_steps.is_none()
+            && self.deadline.is_none()
+            && !self.trace
+            && self.on_op.is_none()
+        {
+            self.dispatch()?;
+        }
+        while self.step()? {}
+
+        // Cells are only added when the data pointer reaches them
+        let max_dp = self.cells.len() - 1;
+
+        Ok(RunStats::new(
+            &self.ops,
+            &self.executions,
+            self.jumps_taken,
+            max_dp,
+        ))
+    }
+
+    /// Execute ops until the program finishes, without the checks [`step`](Self::step)
+    /// makes for step limits, timeouts, tracing and hooks.
+    fn dispatch(&mut self) -> Result<(), ExecutionError> {
+        let mut ip = self.ip;
+        while let Some(&handler) = self.handlers.get(ip) {
+            self.steps += 1;
+            self.executions[ip] += 1;
+            ip = match handler(self, ip) {
+                Ok(next) => next,
+                Err(err) => {
+                    self.ip = ip;
+                    return Err(err);
+                }
+            };
+        }
+
+        self.ip = ip;
+        Ok(())
+    }
+
+    /// Execute the next op, returning `false` without doing anything once the program
+    /// has finished. After an error [`Interpreter::ip`] is the op that failed.
+    ///
+    /// Output stays buffered until [`Interpreter::flush`], input or the end of [`Interpreter::run`].
+    #[inline(always)]
+    pub fn step(&mut self) -> Result<bool, ExecutionError> {
+        let (ip, dp) = (self.ip, self.dp);
+        if ip >= self.ops.len() {
+            return Ok(false);
+        }
+
+        if let Some(limit) = self.max_steps {
+            if self.steps == limit {
+                return Err(ExecutionError::StepLimitExceeded { limit });
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(ExecutionError::Timeout {
+                    timeout: self.timeout.unwrap(),
+                });
+            }
+        }
+        if self.trace && self.trace_limit.is_none_or(|limit| self.steps < limit) {
+            eprintln!(
+                "ip={} dp={} Op::{} cell={}",
+                ip,
+                dp,
+                self.ops[ip].name(),
+                self.cells[dp]
+            );
+        }
+        if let Some(hook) = &mut self.on_op {
+            hook(ip, dp, &self.ops[ip], self.cells.make_contiguous());
+        }
+        self.steps += 1;
+        self.executions[ip] += 1;
+        self.ip = (self.handlers[ip])(self, ip)?;
+        Ok(true)
+    }
+
+    /// Write out any buffered output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// The ops being executed.
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Index of the next op to execute.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Index of the current cell.
+    pub fn dp(&self) -> usize {
+        self.dp
+    }
+
+    /// Value of the cell at `index`. Cells the tape hasn't grown to yet are zero.
+    pub fn cell(&self, index: usize) -> C {
+        self.cells.get(index).copied().unwrap_or(C::ZERO)
+    }
+
+    /// Every cell the tape has grown to. The tape only grows when the data pointer moves
+    /// onto a new cell, so this ends at the furthest cell it reached.
+    pub fn tape(&mut self) -> &[C] {
+        self.cells.make_contiguous()
+    }
+
+    /// Capture the instruction pointer, data pointer and tape.
+    pub fn snapshot(&self) -> VmState<C> {
+        VmState {
+            ip: self.ip,
+            dp: self.dp,
+            tape: self.cells.iter().copied().collect(),
+        }
+    }
+
+    /// Go back to a state taken with [`snapshot`](Self::snapshot), so execution continues
+    /// from there.
+    ///
+    /// Only the program's state is restored: output already written stays written, input
+    /// already read stays consumed, and the step counters keep counting.
+    ///
+    /// Panics if `state` doesn't fit this program, i.e. its instruction pointer is past the
+    /// end of the ops or its data pointer is off its tape.
+    pub fn restore(&mut self, state: &VmState<C>) {
+        assert!(
+            state.ip <= self.ops.len(),
+            "snapshot ip is past the program"
+        );
+        assert!(state.dp < state.tape.len(), "snapshot dp is off its tape");
+
+        self.ip = state.ip;
+        self.dp = state.dp;
+        self.cells = state.tape.iter().copied().collect();
+    }
+
+    /// Append `ops` to the program, which carries on from the first of them with the same
+    /// tape and data pointer. Whatever was left of the earlier ops, for example after an
+    /// error, is skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `[` and `]` in `ops` aren't balanced.
+    pub fn extend(&mut self, ops: Vec<Op>) {
+        self.ip = self.ops.len();
+        self.ops.extend(ops);
+        self.handlers = Self::handlers(&self.ops);
+        self.jump_table = jump_table(&self.ops);
+        self.executions.resize(self.ops.len(), 0);
+    }
+
+    /// The handler for each of `ops`.
+    fn handlers(ops: &[Op]) -> Vec<Handler<R, W, C>> {
+        codegen(
+            Handlers {
+                handlers: Vec::with_capacity(ops.len()),
+                loops: vec![],
+            },
+            ops,
+        )
+    }
+
+    fn inc(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let dp = self.dp;
+        self.cells[dp] = self.cells[dp].wrapping_add(C::ONE);
+        Ok(ip + 1)
+    }
+
+    fn dec(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let dp = self.dp;
+        self.cells[dp] = self.cells[dp].wrapping_sub(C::ONE);
+        Ok(ip + 1)
+    }
+
+    fn move_left_one(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        self.dp = self.move_left(ip, self.dp, 1)?;
+        Ok(ip + 1)
+    }
+
+    fn move_right_one(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        self.dp = self.move_right(self.dp, 1)?;
+        Ok(ip + 1)
+    }
+
+    fn output_one(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let cell = self.cells[self.dp];
+        if let Some(hook) = &mut self.on_output {
+            hook(OutputEvent {
+                byte: cell.to_byte(),
+                ip,
+                dp: self.dp,
+            });
+            return Ok(ip + 1);
+        }
+        match self.output_mode {
+            OutputMode::Raw => self.writer.write_all(&[cell.to_byte()])?,
+            OutputMode::Decimal => write!(self.writer, "{} ", cell)?,
+        }
+        Ok(ip + 1)
+    }
+
+    fn output_repeat(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let Op::OutputRepeat(count) = self.ops[ip] else {
+            unreachable!()
+        };
+        let cell = self.cells[self.dp];
+        if let Some(hook) = &mut self.on_output {
+            for _ in 0..count {
+                hook(OutputEvent {
+                    byte: cell.to_byte(),
+                    ip,
+                    dp: self.dp,
+                });
+            }
+            return Ok(ip + 1);
+        }
+        // Written a batch at a time, so a long run doesn't need a buffer as long as itself
+        let batch = count.min(OUTPUT_BATCH);
+        let bytes = match self.output_mode {
+            OutputMode::Raw => vec![cell.to_byte(); batch],
+            OutputMode::Decimal => format!("{} ", cell).repeat(batch).into_bytes(),
+        };
+        let mut remaining = count;
+        while remaining > 0 {
+            let written = remaining.min(batch);
+            self.writer
+                .write_all(&bytes[..bytes.len() / batch * written])?;
+            remaining -= written;
+        }
+        Ok(ip + 1)
+    }
+
+    fn input(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let dp = self.dp;
+        // Make sure any prompt is visible before blocking on input
+        self.writer.flush()?;
+
+        let mut read = [0; 1];
+        if self.reader.read(&mut read)? == 1 {
+            self.cells[dp] = C::from_byte(read[0]);
+        } else {
+            match self.eof_policy {
+                EofPolicy::Unchanged => {}
+                EofPolicy::Zero => self.cells[dp] = C::ZERO,
+                EofPolicy::NegativeOne => self.cells[dp] = C::MAX,
+            }
+        }
+        Ok(ip + 1)
+    }
+
+    fn jump_if_zero(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        if self.cells[self.dp] == C::ZERO {
+            self.jumps_taken += 1;
+            return Ok(self.jump_table[ip]);
+        }
+        Ok(ip + 1)
+    }
+
+    fn jump_if_non_zero(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        if self.cells[self.dp] != C::ZERO {
+            self.jumps_taken += 1;
+            return Ok(self.jump_table[ip]);
+        }
+        Ok(ip + 1)
+    }
+
+    /// `[` of an empty loop, which spins forever unless it's skipped.
+    fn empty_loop(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        if self.cells[self.dp] != C::ZERO {
+            return Err(ExecutionError::InfiniteLoop);
+        }
+        self.jump_if_zero(ip)
+    }
+
+    fn add(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let Op::Add(delta) = self.ops[ip] else {
+            unreachable!()
+        };
+        let dp = self.dp;
+        let amount = C::from_byte(delta.unsigned_abs());
+        self.cells[dp] = if delta >= 0 {
+            self.cells[dp].wrapping_add(amount)
+        } else {
+            self.cells[dp].wrapping_sub(amount)
+        };
+        Ok(ip + 1)
+    }
+
+    fn move_pointer(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let Op::Move(delta) = self.ops[ip] else {
+            unreachable!()
+        };
+        self.dp = if delta < 0 {
+            self.move_left(ip, self.dp, delta.unsigned_abs())?
+        } else {
+            self.move_right(self.dp, delta as usize)?
+        };
+        Ok(ip + 1)
+    }
+
+    fn set_zero(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        self.cells[self.dp] = C::ZERO;
+        Ok(ip + 1)
+    }
+
+    fn set_const(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let Op::SetConst(value) = self.ops[ip] else {
+            unreachable!()
+        };
+        let amount = C::from_byte(value.unsigned_abs());
+        self.cells[self.dp] = if value >= 0 {
+            amount
+        } else {
+            C::ZERO.wrapping_sub(amount)
+        };
+        Ok(ip + 1)
+    }
+
+    fn add_mul_op(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let Op::AddMul { offset, factor } = self.ops[ip] else {
+            unreachable!()
+        };
+        // The loop body never runs on a zero cell, so it can't move out of bounds
+        if self.cells[self.dp] != C::ZERO {
+            self.dp = self.add_mul(ip, self.dp, offset, factor)?;
+        }
+        Ok(ip + 1)
+    }
+
+    fn add_at_offset_op(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let Op::AddAtOffset { offset, delta } = self.ops[ip] else {
+            unreachable!()
+        };
+        self.dp = self.add_at_offset(ip, self.dp, offset, delta)?;
+        Ok(ip + 1)
+    }
+
+    // With no zero anywhere on a wrapping tape the original loop never ends, so neither
+    // does the scan
+    fn scan_right_op(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        match self.scan_right(self.dp)? {
+            Some(dp) => {
+                self.dp = dp;
+                Ok(ip + 1)
+            }
+            None => Ok(ip),
+        }
+    }
+
+    fn scan_left_op(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        match self.scan_left(ip, self.dp)? {
+            Some(dp) => {
+                self.dp = dp;
+                Ok(ip + 1)
+            }
+            None => Ok(ip),
+        }
+    }
+
+    fn debug_dump(&mut self, ip: usize) -> Result<usize, ExecutionError> {
+        let dp = self.dp;
+        // 10 cells centered on the data pointer, with the current one bracketed
+        let start = dp.saturating_sub(5);
+        let cells: Vec<String> = (start..start + 10)
+            .map(|index| {
+                if index == dp {
+                    format!("[{}]", self.cell(index))
+                } else {
+                    self.cell(index).to_string()
+                }
+            })
+            .collect();
+        eprintln!("# dp={} cells {}..: {}", dp, start, cells.join(" "));
+        Ok(ip + 1)
+    }
+
+    /// Add `factor` times the cell at `dp` to the cell `offset` away, returning the data
+    /// pointer, which shifts if the tape grows to the left.
+    fn add_mul(
+        &mut self,
+        ip: usize,
+        dp: usize,
+        offset: isize,
+        factor: i8,
+    ) -> Result<usize, ExecutionError> {
+        let (dp, target) = self.offset_cell(ip, dp, offset)?;
+
+        let amount = self.cells[dp].wrapping_mul(C::from_byte(factor.unsigned_abs()));
+        self.cells[target] = if factor >= 0 {
+            self.cells[target].wrapping_add(amount)
+        } else {
+            self.cells[target].wrapping_sub(amount)
+        };
+
+        Ok(dp)
+    }
+
+    /// Add `delta` to the cell `offset` away from `dp`, returning the data pointer, which
+    /// shifts if the tape grows to the left.
+    fn add_at_offset(
+        &mut self,
+        ip: usize,
+        dp: usize,
+        offset: isize,
+        delta: i8,
+    ) -> Result<usize, ExecutionError> {
+        let (dp, target) = self.offset_cell(ip, dp, offset)?;
+
+        let amount = C::from_byte(delta.unsigned_abs());
+        self.cells[target] = if delta >= 0 {
+            self.cells[target].wrapping_add(amount)
+        } else {
+            self.cells[target].wrapping_sub(amount)
+        };
+
+        Ok(dp)
+    }
+
+    /// Make sure the tape reaches the cell `offset` away from `dp`, returning the data
+    /// pointer and the index of that cell.
+    fn offset_cell(
+        &mut self,
+        ip: usize,
+        dp: usize,
+        offset: isize,
+    ) -> Result<(usize, usize), ExecutionError> {
+        let len = self.cells.len();
+        let target = if offset < 0 {
+            self.move_left(ip, dp, offset.unsigned_abs())?
+        } else {
+            self.move_right(dp, offset as usize)?
+        };
+        // Cells added when moving left go on the front of the tape and shift the origin
+        let dp = if offset < 0 && self.pointer_mode == PointerMode::Grow {
+            dp + self.cells.len() - len
+        } else {
+            dp
+        };
+
+        Ok((dp, target))
+    }
+
+    /// Move the data pointer right of `dp` to the nearest zero cell, returning the new
+    /// data pointer, or `None` if a wrapping tape has no zero cell.
+    fn scan_right(&mut self, dp: usize) -> Result<Option<usize>, ExecutionError> {
+        let len = self.cells.len();
+        let cells = self.cells.make_contiguous();
+        if let Some(distance) = C::find_zero(&cells[dp..]) {
+            return Ok(Some(dp + distance));
+        }
+        if self.pointer_mode == PointerMode::Wrap && len == self.max_cells {
+            return Ok(C::find_zero(&cells[..dp]));
+        }
+
+        // Every cell past the end of the tape is zero
+        self.move_right(dp, len - dp).map(Some)
+    }
+
+    /// Move the data pointer left of `dp` to the nearest zero cell, returning the new
+    /// data pointer, or `None` if a wrapping tape has no zero cell.
+    fn scan_left(&mut self, ip: usize, dp: usize) -> Result<Option<usize>, ExecutionError> {
+        let len = self.cells.len();
+        let cells = self.cells.make_contiguous();
+        if let Some(index) = C::rfind_zero(&cells[..=dp]) {
+            return Ok(Some(index));
+        }
+        if self.pointer_mode == PointerMode::Wrap && len == self.max_cells {
+            return Ok(C::rfind_zero(&cells[dp + 1..]).map(|index| dp + 1 + index));
+        }
+
+        self.move_left(ip, dp, dp + 1).map(Some)
+    }
+
+    /// Move the data pointer `n` cells left of `dp` for the op at `ip`, returning the new
+    /// data pointer.
+    fn move_left(&mut self, ip: usize, dp: usize, n: usize) -> Result<usize, ExecutionError> {
+        if n <= dp {
+            return Ok(dp - n);
+        }
+
+        match self.pointer_mode {
+            PointerMode::Strict => return Err(ExecutionError::PointerUnderflow { ip }),
+            PointerMode::Grow => {}
+            PointerMode::Wrap => {
+                let dp = self.max_cells - 1 - (n - dp - 1) % self.max_cells;
+                self.reach(dp);
+                return Ok(dp);
+            }
+        }
+
+        let missing = n - dp;
+        if self.cells.len() + missing > self.max_cells {
+            return Err(ExecutionError::TapeOverflow { dp });
+        }
+
+        // New cells are inserted at the front in one go, so the data pointer lands on
+        // index 0
+        let len = self.cells.len();
+        self.cells.resize(len + missing, C::ZERO);
+        self.cells.rotate_right(missing);
+
+        Ok(0)
+    }
+
+    /// Move the data pointer `n` cells right of `dp`, returning the new data pointer.
+    fn move_right(&mut self, dp: usize, n: usize) -> Result<usize, ExecutionError> {
+        let mut dp = dp + n;
+
+        if dp >= self.max_cells {
+            if self.pointer_mode != PointerMode::Wrap {
+                return Err(ExecutionError::TapeOverflow { dp });
+            }
+            dp %= self.max_cells;
+        }
+        self.reach(dp);
+
+        Ok(dp)
+    }
+
+    /// Grow the tape with zeroed cells until it includes the cell at `index`.
+    fn reach(&mut self, index: usize) {
+        if index >= self.cells.len() {
+            self.cells.resize(index + 1, C::ZERO);
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use super::{CompileError, OUTPUT_BATCH};
+use crate::{
+    aarch64::{self, AddImmediate},
+    codegen::CodeGen,
+};
+
+/// movz x16, #0x04 (write syscall)
+#[cfg(target_os = "macos")]
+const WRITE_SYSCALL: [u8; 4] = [0x90, 0x00, 0x80, 0xD2];
+/// movz x16, #0x03 (read syscall)
+#[cfg(target_os = "macos")]
+const READ_SYSCALL: [u8; 4] = [0x70, 0x00, 0x80, 0xD2];
+
+/// movz x8, #0x40 (write syscall)
+#[cfg(target_os = "linux")]
+const WRITE_SYSCALL: [u8; 4] = [0x08, 0x08, 0x80, 0xD2];
+/// movz x8, #0x3F (read syscall)
+#[cfg(target_os = "linux")]
+const READ_SYSCALL: [u8; 4] = [0xE8, 0x07, 0x80, 0xD2];
+
+/// cmp x4, #0 (Linux returns -errno when a syscall fails)
+#[cfg(target_os = "linux")]
+const TEST_WRITE: &[u8] = &[0x9F, 0x00, 0x00, 0xF1];
+/// B.LT / B.GE, #8
+#[cfg(target_os = "linux")]
+const BRANCH_IF_FAILED: (u32, u32) = (0x5400000B, 0x5400004A);
+/// B.LT / B.GE, #8 (The flags from checking for EINTR are replaced by `TEST_WRITE`)
+#[cfg(target_os = "linux")]
+const BRANCH_IF_READ_FAILED: (u32, u32) = BRANCH_IF_FAILED;
+/// mov w0, w4 (X4 already holds -errno)
+#[cfg(target_os = "linux")]
+const IO_FAILED: [u8; 4] = [0xE0, 0x03, 0x04, 0x2A];
+
+/// cmn x0, #4 (Linux returns -errno when a syscall fails, so this compares with -EINTR)
+#[cfg(target_os = "linux")]
+const CHECK_INTERRUPTED: &[u8] = &[0x1F, 0x10, 0x00, 0xB1];
+
+/// Nothing to do: macOS sets the carry flag when a syscall fails
+#[cfg(target_os = "macos")]
+const TEST_WRITE: &[u8] = &[];
+/// B.CS / B.CC, #8
+#[cfg(target_os = "macos")]
+const BRANCH_IF_FAILED: (u32, u32) = (0x54000002, 0x54000043);
+/// B.AL / NOP (Only reached after a failed read, and comparing with EINTR lost the carry)
+#[cfg(target_os = "macos")]
+const BRANCH_IF_READ_FAILED: (u32, u32) = (0x5400000E, 0xD503201F);
+/// neg w0, w4 (X4 holds errno, which is returned negated like on Linux)
+#[cfg(target_os = "macos")]
+const IO_FAILED: [u8; 4] = [0xE0, 0x03, 0x04, 0x4B];
+
+/// b.cc #20; cmp x0, #4 (macOS sets the carry flag when a syscall fails and returns errno
+/// in X0, so this only compares with EINTR after a failure. A successful read skips the
+/// retry and the failure branch that follow)
+#[cfg(target_os = "macos")]
+const CHECK_INTERRUPTED: &[u8] = &[0xA3, 0x00, 0x00, 0x54, 0x1F, 0x10, 0x00, 0xF1];
+
+/// Condition under which a loop branch is taken.
+#[derive(Clone, Copy)]
+enum Branch {
+    Zero,
+    NonZero,
+    /// The `write` tested by `TEST_WRITE` failed
+    WriteFailed,
+    /// The `read` whose result was moved to X4 failed with something other than `EINTR`
+    ReadFailed,
+}
+
+/// AArch64 code generator. The data pointer lives in X0, which is also the first argument.
+///
+/// The start of the tape is kept in X9 so the value of the first cell can be returned.
+/// With bounds checks the tape length comes in X1, and the tape is kept as the range
+/// `[X9, X10)` since X1 is used as scratch.
+///
+/// A failed `write`, e.g. once the reader of a pipe is gone, returns -errno right away
+/// instead of carrying on with nowhere for the output to go, and so does a failed `read`
+/// rather than carrying on with a stale cell. A `read` that fails with `EINTR` is
+/// retried. Failures show in X0 as -errno on Linux, and on macOS as the carry flag with
+/// errno in X0.
+pub struct Aarch64 {
+    code: Vec<u8>,
+    /// Offsets of the branch slot emitted for each `[` or if still waiting for its end
+    loops: Vec<usize>,
+    /// Offsets of the branch slot emitted after each `read` and `write`, with the
+    /// condition it branches on, patched by `finish`
+    io_checks: Vec<(usize, Branch)>,
+    bounds_checks: bool,
+    /// Whether a branch didn't reach its target, which `finish` reports
+    out_of_range: bool,
+}
+
+impl Aarch64 {
+    pub fn new(bounds_checks: bool) -> Self {
+        // mov x9, x0 (Start of the tape)
+        let mut code = vec![0xE9, 0x03, 0x00, 0xAA];
+        if bounds_checks {
+            // add x10, x0, x1 (End of the tape)
+            code.extend_from_slice(&[0x0A, 0x00, 0x01, 0x8B]);
+        }
+
+        Self {
+            code,
+            loops: vec![],
+            io_checks: vec![],
+            bounds_checks,
+            out_of_range: false,
+        }
+    }
+
+    /// Trap with `BRK` unless the address in `Xn` is on the tape.
+    fn check_bounds(&mut self, rn: u32) {
+        if !self.bounds_checks {
+            return;
+        }
+
+        // CMP Xn, X9
+        let below = 0xEB09001F | rn << 5;
+        // CMP Xn, X10
+        let above = 0xEB0A001F | rn << 5;
+
+        self.code.extend_from_slice(&below.to_le_bytes());
+        // B.LO <brk>
+        self.code.extend_from_slice(&[0x63, 0x00, 0x00, 0x54]);
+        self.code.extend_from_slice(&above.to_le_bytes());
+        self.code.extend_from_slice(&[
+            0x43, 0x00, 0x00, 0x54, // b.lo <after brk>
+            0x20, 0x00, 0x20, 0xD4, // brk #1
+        ]);
+    }
+
+    /// Fill the two-instruction branch slot at `at` so it jumps to `target` when W1 is zero
+    /// (`CBZ`) or nonzero (`CBNZ`), or when a `read` or `write` failed (`B.cond`).
+    ///
+    /// CBZ/CBNZ and B.cond take a signed 19-bit word displacement (bits [23:5]) and only reach
+    /// ±1MB, which is filled out with a NOP. Farther targets flip the condition to skip
+    /// over an unconditional `B`, whose 26-bit displacement reaches ±128MB. Targets past
+    /// that leave the slot empty and make `finish` fail.
+    fn patch_branch(&mut self, at: usize, target: usize, condition: Branch) {
+        let (near, far) = match condition {
+            // CBZ W1 / CBNZ W1, #8
+            Branch::Zero => (0x34000001u32, 0x35000041u32),
+            // CBNZ W1 / CBZ W1, #8
+            Branch::NonZero => (0x35000001, 0x34000041),
+            Branch::WriteFailed => BRANCH_IF_FAILED,
+            Branch::ReadFailed => BRANCH_IF_READ_FAILED,
+        };
+
+        let displacement = (target as isize - at as isize) / 4;
+        let (first, second) = if (-(1 << 18)..1 << 18).contains(&displacement) {
+            // NOP
+            (near | ((displacement as u32) & 0x7FFFF) << 5, 0xD503201F)
+        } else {
+            let displacement = displacement - 1;
+            if !(-(1 << 25)..1 << 25).contains(&displacement) {
+                self.out_of_range = true;
+                return;
+            }
+            // B <target>
+            (far, 0x14000000 | (displacement as u32) & 0x3FFFFFF)
+        };
+
+        self.code[at..at + 4].copy_from_slice(&first.to_le_bytes());
+        self.code[at + 4..at + 8].copy_from_slice(&second.to_le_bytes());
+    }
+
+    /// Branch to the I/O failure exit if the syscall whose result was saved in X4
+    /// failed. Only MOVs and ADDs that don't set flags may come between the syscall and
+    /// this check, so the flags survive on macOS.
+    fn check_write(&mut self) {
+        self.check_io(Branch::WriteFailed);
+    }
+
+    /// Branch to the I/O failure exit after a `read` whose result was saved in X4 failed
+    /// with something other than `EINTR`.
+    fn check_read(&mut self) {
+        self.check_io(Branch::ReadFailed);
+    }
+
+    fn check_io(&mut self, condition: Branch) {
+        self.code.extend_from_slice(TEST_WRITE);
+        self.io_checks.push((self.code.len(), condition));
+        self.code.extend_from_slice(&[0; 8]);
+    }
+
+    /// Emit `Xd = Xn + delta` with the instructions [`aarch64::add_immediate`] picks.
+    fn add_immediate(&mut self, rd: u32, rn: u32, delta: isize) {
+        let negative = delta < 0;
+
+        match aarch64::add_immediate(delta.unsigned_abs()) {
+            AddImmediate::Register(magnitude) => {
+                for (halfword, shift) in aarch64::load_immediate(magnitude) {
+                    // MOVZ X11, #halfword  or  MOVK X11, #halfword, LSL #shift
+                    let opcode: u32 = if shift == 0 { 0xD280000B } else { 0xF280000B };
+                    let instruction = opcode | (shift / 16) << 21 | halfword << 5;
+                    self.code.extend_from_slice(&instruction.to_le_bytes());
+                }
+                // ADD Xd, Xn, X11  or  SUB Xd, Xn, X11
+                let opcode: u32 = if negative { 0xCB000000 } else { 0x8B000000 };
+                let instruction = opcode | 11 << 16 | rn << 5 | rd;
+                self.code.extend_from_slice(&instruction.to_le_bytes());
+            }
+            AddImmediate::Immediates { shifted, unshifted } => {
+                // ADD Xd, Xn, #imm{, LSL #12}  or  SUB Xd, Xn, #imm{, LSL #12}
+                let opcode: u32 = if negative { 0xD1000000 } else { 0x91000000 };
+                let mut rn = rn;
+                if let Some(high) = shifted {
+                    let instruction = opcode | 1 << 22 | high << 10 | rn << 5 | rd;
+                    self.code.extend_from_slice(&instruction.to_le_bytes());
+                    rn = rd;
+                }
+                if let Some(low) = unshifted {
+                    let instruction = opcode | low << 10 | rn << 5 | rd;
+                    self.code.extend_from_slice(&instruction.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+impl CodeGen for Aarch64 {
+    type Output = Result<Vec<u8>, CompileError>;
+
+    fn inc(&mut self) {
+        // LDRB W1, [X0]    ; Load the byte at the memory address pointed to by X0 into W1
+        // ADD W1, W1, #1   ; Add 1 to the value in W1
+        // STRB W1, [X0]    ; Store the modified byte back to the memory address in X0
+        self.code.extend_from_slice(&[
+            0x01, 0x00, 0x40, 0x39, 0x21, 0x04, 0x00, 0x11, 0x01, 0x00, 0x00, 0x39,
+        ]);
+    }
+
+    fn dec(&mut self) {
+        // LDRB W1, [X0]    ; Load the byte at the memory address pointed to by X0 into W1
+        // SUB W1, W1, #1   ; Subtract 1 from the value in W1
+        // STRB W1, [X0]    ; Store the modified byte back to the memory address in X0
+        self.code.extend_from_slice(&[
+            0x01, 0x00, 0x40, 0x39, 0x21, 0x04, 0x00, 0x51, 0x01, 0x00, 0x00, 0x39,
+        ]);
+    }
+
+    fn move_left(&mut self) {
+        // SUB X0, X0, #1
+        self.code.extend_from_slice(&[0x00, 0x04, 0x00, 0xD1]);
+        self.check_bounds(0);
+    }
+
+    fn move_right(&mut self) {
+        // ADD X0, X0, #1
+        self.code.extend_from_slice(&[0x00, 0x04, 0x00, 0x91]);
+        self.check_bounds(0);
+    }
+
+    fn output(&mut self) {
+        self.code.extend_from_slice(&[
+            0xE3, 0x03, 0x00, 0xAA, // mov x3, x0 (Save x0, our data pointer)
+            0x20, 0x00, 0x80, 0xD2, // movz x0, #0x01 (STD OUT)
+            0xE1, 0x03, 0x03, 0xAA, // mov x1, x3 (Data pointer)
+            0x22, 0x00, 0x80, 0xD2, // movz x2, #0x1 (Always 1 byte output)
+        ]);
+        self.code.extend_from_slice(&WRITE_SYSCALL);
+        self.code.extend_from_slice(&[
+            0x01, 0x00, 0x00, 0xD4, // svc #0
+            0xE4, 0x03, 0x00, 0xAA, // mov x4, x0 (Result)
+            0xE0, 0x03, 0x03, 0xAA, // mov x0 x3 (Restore data pointer)
+        ]);
+        self.check_write();
+    }
+
+    fn output_repeat(&mut self, count: usize) {
+        let mut remaining = count;
+        while remaining > 0 {
+            let batch = remaining.min(OUTPUT_BATCH) as u32;
+            // SP must stay 16-byte aligned
+            let reserved = (batch + 15) & !15;
+
+            // SUB SP, SP, #reserved
+            let reserve = 0xD10003FF | reserved << 10;
+            // ADD SP, SP, #reserved
+            let release = 0x910003FF | reserved << 10;
+            // MOVZ X5, #batch
+            let counter = 0xD2800005 | batch << 5;
+            // MOVZ X2, #batch
+            let length = 0xD2800002 | batch << 5;
+
+            // Fill a stack buffer with copies of the cell and write it in one syscall
+            self.code.extend_from_slice(&[
+                0xE3, 0x03, 0x00, 0xAA, // mov x3, x0 (Save x0, our data pointer)
+                0x01, 0x00, 0x40, 0x39, // ldrb w1, [x0] (Byte to repeat)
+            ]);
+            self.code.extend_from_slice(&reserve.to_le_bytes());
+            self.code.extend_from_slice(&counter.to_le_bytes());
+            self.code.extend_from_slice(&[
+                0xA5, 0x04, 0x00, 0xD1, // sub x5, x5, #1
+                0xE1, 0x6B, 0x25, 0x38, // strb w1, [sp, x5]
+                0xC5, 0xFF, 0xFF, 0xB5, // cbnz x5, #-8
+                0x20, 0x00, 0x80, 0xD2, // movz x0, #0x01 (STD OUT)
+                0xE1, 0x03, 0x00, 0x91, // mov x1, sp (Buffer)
+            ]);
+            self.code.extend_from_slice(&length.to_le_bytes());
+            self.code.extend_from_slice(&WRITE_SYSCALL);
+            self.code.extend_from_slice(&[
+                0x01, 0x00, 0x00, 0xD4, // svc #0
+                0xE4, 0x03, 0x00, 0xAA, // mov x4, x0 (Result)
+            ]);
+            self.code.extend_from_slice(&release.to_le_bytes());
+            self.code.extend_from_slice(&[
+                0xE0, 0x03, 0x03, 0xAA, // mov x0 x3 (Restore data pointer)
+            ]);
+            self.check_write();
+
+            remaining -= batch as usize;
+        }
+    }
+
+    fn input(&mut self) {
+        self.code.extend_from_slice(&[
+            0xE3, 0x03, 0x00, 0xAA, // mov x3, x0 (Save x0, our data pointer)
+        ]);
+        // A read interrupted by a signal before it got any input is issued again, with
+        // every argument reloaded since X0 now holds the result
+        let retry = self.code.len();
+        self.code.extend_from_slice(&[
+            0x00, 0x00, 0x80, 0xD2, // movz x0, #0x00 (STD IN)
+            0xE1, 0x03, 0x03, 0xAA, // mov x1, x3 (Data pointer)
+            0x22, 0x00, 0x80, 0xD2, // movz x2, #0x1 (Always 1 byte input)
+        ]);
+        self.code.extend_from_slice(&READ_SYSCALL);
+        self.code.extend_from_slice(&[0x01, 0x00, 0x00, 0xD4]); // svc #0
+        self.code.extend_from_slice(CHECK_INTERRUPTED);
+        let displacement = (retry as isize - self.code.len() as isize) / 4;
+        // B.EQ retry
+        let instruction = 0x54000000 | ((displacement as u32) & 0x7FFFF) << 5;
+        self.code.extend_from_slice(&instruction.to_le_bytes());
+        self.code.extend_from_slice(&[
+            0xE4, 0x03, 0x00, 0xAA, // mov x4, x0 (Result)
+        ]);
+        self.check_read();
+        self.code.extend_from_slice(&[
+            0x40, 0x00, 0x00, 0xB5, // cbnz x0, #8 (Bytes read)
+            0x7F, 0x00, 0x00, 0x39, // strb wzr, [x3] (EOF)
+            0xE0, 0x03, 0x03, 0xAA, // mov x0 x3 (Restore data pointer)
+        ]);
+    }
+
+    fn add(&mut self, delta: i8) {
+        // ADD W1, W1, #imm  or  SUB W1, W1, #imm
+        let opcode: u32 = if delta >= 0 { 0x11000021 } else { 0x51000021 };
+        let instruction = opcode | (delta.unsigned_abs() as u32) << 10;
+
+        // LDRB W1, [X0]    ; Load the byte at the memory address pointed to by X0 into W1
+        self.code.extend_from_slice(&[0x01, 0x00, 0x40, 0x39]);
+        self.code.extend_from_slice(&instruction.to_le_bytes());
+        // STRB W1, [X0]    ; Store the modified byte back to the memory address in X0
+        self.code.extend_from_slice(&[0x01, 0x00, 0x00, 0x39]);
+    }
+
+    fn move_pointer(&mut self, delta: isize) {
+        self.add_immediate(0, 0, delta);
+        self.check_bounds(0);
+    }
+
+    fn set_zero(&mut self) {
+        // STRB WZR, [X0]   ; Store the zero register to the memory address in X0
+        self.code.extend_from_slice(&[0x1F, 0x00, 0x00, 0x39]);
+    }
+
+    fn set_const(&mut self, value: i8) {
+        // MOVZ W1, #value
+        let load = 0x52800001 | (value as u8 as u32) << 5;
+
+        self.code.extend_from_slice(&load.to_le_bytes());
+        // STRB W1, [X0]    ; Store it to the memory address in X0
+        self.code.extend_from_slice(&[0x01, 0x00, 0x00, 0x39]);
+    }
+
+    fn add_mul(&mut self, offset: isize, factor: i8) {
+        // MOVZ W2, #|factor|
+        let factor_instruction = 0x52800002 | (factor.unsigned_abs() as u32) << 5;
+
+        // LDRB W1, [X0]    ; Load the current cell
+        self.code.extend_from_slice(&[0x01, 0x00, 0x40, 0x39]);
+        self.code
+            .extend_from_slice(&factor_instruction.to_le_bytes());
+        // MUL W2, W1, W2   ; Multiply it by the factor
+        self.code.extend_from_slice(&[0x22, 0x7C, 0x02, 0x1B]);
+        // X4 = X0 + offset ; Address of the target cell
+        self.add_immediate(4, 0, offset);
+        self.check_bounds(4);
+        // LDRB W3, [X4]    ; Load the target cell
+        self.code.extend_from_slice(&[0x83, 0x00, 0x40, 0x39]);
+        if factor >= 0 {
+            // ADD W3, W3, W2
+            self.code.extend_from_slice(&[0x63, 0x00, 0x02, 0x0B]);
+        } else {
+            // SUB W3, W3, W2
+            self.code.extend_from_slice(&[0x63, 0x00, 0x02, 0x4B]);
+        }
+        // STRB W3, [X4]    ; Store the target cell
+        self.code.extend_from_slice(&[0x83, 0x00, 0x00, 0x39]);
+    }
+
+    fn add_at_offset(&mut self, offset: isize, delta: i8) {
+        // ADD W3, W3, #imm  or  SUB W3, W3, #imm
+        let opcode: u32 = if delta >= 0 { 0x11000063 } else { 0x51000063 };
+        let instruction = opcode | (delta.unsigned_abs() as u32) << 10;
+
+        // X4 = X0 + offset ; Address of the target cell
+        self.add_immediate(4, 0, offset);
+        self.check_bounds(4);
+        // LDRB W3, [X4]    ; Load the target cell
+        self.code.extend_from_slice(&[0x83, 0x00, 0x40, 0x39]);
+        self.code.extend_from_slice(&instruction.to_le_bytes());
+        // STRB W3, [X4]    ; Store the target cell
+        self.code.extend_from_slice(&[0x83, 0x00, 0x00, 0x39]);
+    }
+
+    fn loop_start(&mut self) {
+        // LDRB W1, [X0]    ; Load the byte at the data pointer
+        // CBZ W1, <end>    ; Jump past the matching ] if zero (patched by loop_end)
+        self.code.extend_from_slice(&[0x01, 0x00, 0x40, 0x39]);
+        self.loops.push(self.code.len());
+        self.code.extend_from_slice(&[0; 8]);
+    }
+
+    fn loop_end(&mut self) {
+        let start = self.loops.pop().expect("unbalanced loop");
+
+        // LDRB W1, [X0]    ; Load the byte at the data pointer
+        // CBNZ W1, <body>  ; Jump back after the matching [ if nonzero
+        self.code.extend_from_slice(&[0x01, 0x00, 0x40, 0x39]);
+        let end = self.code.len();
+        self.code.extend_from_slice(&[0; 8]);
+
+        self.patch_branch(end, start + 8, Branch::NonZero);
+        self.patch_branch(start, end + 8, Branch::Zero);
+    }
+
+    fn if_nonzero(&mut self) {
+        // LDRB W1, [X0]    ; Load the byte at the data pointer
+        // CBZ W1, <end>    ; Jump past the matching end_if if zero (patched by end_if)
+        self.code.extend_from_slice(&[0x01, 0x00, 0x40, 0x39]);
+        self.loops.push(self.code.len());
+        self.code.extend_from_slice(&[0; 8]);
+    }
+
+    fn end_if(&mut self) {
+        let start = self.loops.pop().expect("unbalanced if");
+        self.patch_branch(start, self.code.len(), Branch::Zero);
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn finish(mut self) -> Self::Output {
+        self.code.extend_from_slice(&[
+            0x20, 0x01, 0x40, 0x39, // ldrb w0, [x9] (Return the first cell)
+            0xC0, 0x03, 0x5F, 0xD6, // ret
+        ]);
+
+        if !self.io_checks.is_empty() {
+            let failed = self.code.len();
+            self.code.extend_from_slice(&IO_FAILED);
+            self.code.extend_from_slice(&[0xC0, 0x03, 0x5F, 0xD6]); // ret
+            for (at, condition) in std::mem::take(&mut self.io_checks) {
+                self.patch_branch(at, failed, condition);
+            }
+        }
+
+        if self.out_of_range {
+            return Err(CompileError::BranchOutOfRange);
+        }
+        Ok(self.code)
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    fmt::{self, Write},
+    io::{self, Error},
+    ptr,
+};
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+use libc::pthread_jit_write_protect_np;
+
+use crate::{
+    codegen::{codegen, codegen_with_ranges, CodeGen},
+    Op,
+};
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+mod object_file;
+mod tape;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+/// Largest run of output a single write copies onto the stack. Longer runs take
+/// several writes.
+const OUTPUT_BATCH: usize = 2048;
+
+pub use tape::{GuardedTape, Tape};
+
+#[cfg(target_arch = "aarch64")]
+type Native = aarch64::Aarch64;
+#[cfg(target_arch = "x86_64")]
+type Native = x86_64::X86_64;
+
+/// Why the JIT couldn't compile a program.
+#[derive(Debug)]
+pub enum CompileError {
+    /// A branch can't reach its target, such as the end of a loop whose body is more
+    /// code than the architecture's branches span.
+    BranchOutOfRange,
+    /// A pointer move or cell offset is larger than an instruction can encode.
+    OffsetOutOfRange,
+    /// The system wouldn't map the code into executable memory.
+    Map(io::Error),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::BranchOutOfRange => write!(f, "branch target out of range"),
+            CompileError::OffsetOutOfRange => write!(f, "offset out of range"),
+            CompileError::Map(err) => write!(f, "cannot map the compiled program: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+pub struct JitCompiler {
+    ops: Vec<Op>,
+    bounds_checks: bool,
+}
+
+impl JitCompiler {
+    pub fn new(ops: Vec<Op>) -> Self {
+        Self {
+            ops: fuse_adds(ops),
+            bounds_checks: false,
+        }
+    }
+
+    /// Check every data pointer move against the tape and trap when it leaves the tape,
+    /// instead of reading and writing whatever memory lies past it.
+    pub fn bounds_checks(mut self, bounds_checks: bool) -> Self {
+        self.bounds_checks = bounds_checks;
+        self
+    }
+
+    /// Generate the machine code for the program without mapping it.
+    ///
+    /// Fails if the program is out of reach of the architecture's branches or offsets.
+    pub fn codegen(&self) -> Result<Vec<u8>, CompileError> {
+        codegen(Native::new(self.bounds_checks), &self.ops)
+    }
+
+    /// Generate the machine code for the program as a relocatable object file for the host,
+    /// exporting it as `void bf_main(uint8_t *tape)` to be linked into another program.
+    ///
+    /// Bounds checks need the length of the tape, which `bf_main` isn't given, so the
+    /// object's code never has them.
+    pub fn object(&self) -> Result<Vec<u8>, CompileError> {
+        Ok(object_file::write(&codegen(Native::new(false), &self.ops)?))
+    }
+
+    /// Map the generated code into executable memory. The compiler is left untouched, so
+    /// the same program can be compiled again or from several threads.
+    ///
+    /// Fails if the code can't be generated, or if the system won't map the memory or
+    /// make it executable.
+    pub fn compile(&self) -> Result<CompiledProgram, CompileError> {
+        let code = self.codegen()?;
+        let mem = map_executable(&code).map_err(CompileError::Map)?;
+        let func: extern "C" fn(memory: *mut u8, len: usize) -> i32 =
+            unsafe { std::mem::transmute(mem) };
+
+        Ok(CompiledProgram {
+            mem,
+            len: code.len(),
+            func,
+        })
+    }
+
+    /// List the machine code generated for each op as hex bytes, one op per line, with
+    /// the offset of its first byte.
+    pub fn listing(&self) -> Result<String, CompileError> {
+        let (code, ranges) = codegen_with_ranges(Native::new(self.bounds_checks), &self.ops);
+        let code = code?;
+        let prologue = 0..Native::new(self.bounds_checks).len();
+        let epilogue = ranges.last().map_or(prologue.end, |range| range.end)..code.len();
+
+        let mut lines = vec![];
+        if !prologue.is_empty() {
+            lines.push(("(prologue)".to_string(), prologue));
+        }
+        lines.extend(self.ops.iter().map(|op| format!("{:?}", op)).zip(ranges));
+        lines.push(("(epilogue)".to_string(), epilogue));
+
+        let mut listing = String::new();
+        for (name, range) in lines {
+            let bytes: Vec<String> = code[range.clone()]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            writeln!(
+                listing,
+                "{:06x}  {:<40} {}",
+                range.start,
+                name,
+                bytes.join(" ")
+            )
+            .unwrap();
+        }
+
+        Ok(listing)
+    }
+}
+
+/// Combine runs of [`Op::Add`] into one, modulo 256.
+///
+/// The optimizer splits long runs into several `Add`s so their sum doesn't depend on the
+/// cell width, but the JIT's cells are always bytes, so `+` repeated 300 times is a
+/// single add of 44.
+fn fuse_adds(ops: Vec<Op>) -> Vec<Op> {
+    let mut fused: Vec<Op> = Vec::with_capacity(ops.len());
+
+    for op in ops {
+        match (fused.last_mut(), op) {
+            (Some(Op::Add(delta)), Op::Add(more)) => {
+                *delta = delta.wrapping_add(more);
+                if *delta == 0 {
+                    fused.pop();
+                }
+            }
+            (_, op) => fused.push(op),
+        }
+    }
+
+    fused
+}
+
+/// Makes `MAP_JIT` pages writable for the current thread while alive, and executable
+/// again once dropped, even when leaving early or unwinding from a panic.
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+struct JitWriteGuard;
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+impl JitWriteGuard {
+    fn new() -> Self {
+        unsafe {
+            pthread_jit_write_protect_np(0);
+        }
+        Self
+    }
+}
+
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+impl Drop for JitWriteGuard {
+    fn drop(&mut self) {
+        unsafe {
+            pthread_jit_write_protect_np(1);
+        }
+    }
+}
+
+/// Copy `code` into a freshly mapped executable region.
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+fn map_executable(code: &[u8]) -> io::Result<*mut libc::c_void> {
+    let _writable = JitWriteGuard::new();
+
+    let mem = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            code.len(),
+            libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+            libc::MAP_ANON | libc::MAP_PRIVATE | libc::MAP_JIT,
+            -1,
+            0,
+        )
+    };
+
+    if mem == libc::MAP_FAILED {
+        return Err(Error::last_os_error());
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(code.as_ptr(), mem as *mut u8, code.len());
+    }
+
+    Ok(mem)
+}
+
+/// Copy `code` into a freshly mapped executable region.
+///
+/// Without `MAP_JIT` the pages are written while `RW` and then flipped to `RX`.
+#[cfg(not(all(target_os = "macos", target_arch = "aarch64")))]
+fn map_executable(code: &[u8]) -> io::Result<*mut libc::c_void> {
+    let mem = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            code.len(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_ANON | libc::MAP_PRIVATE,
+            -1,
+            0,
+        )
+    };
+
+    if mem == libc::MAP_FAILED {
+        return Err(Error::last_os_error());
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(code.as_ptr(), mem as *mut u8, code.len());
+
+        if libc::mprotect(mem, code.len(), libc::PROT_READ | libc::PROT_EXEC) != 0 {
+            let err = Error::last_os_error();
+            libc::munmap(mem, code.len());
+            return Err(err);
+        }
+
+        // The instruction cache isn't coherent with data writes on aarch64
+        #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+        __clear_cache(
+            mem as *mut libc::c_char,
+            (mem as *mut libc::c_char).add(code.len()),
+        );
+    }
+
+    Ok(mem)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+extern "C" {
+    fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+}
+
+/// Machine code mapped into executable memory by [`JitCompiler::compile`].
+///
+/// The code only refers to the tape it is handed, so one compiled program can be called
+/// any number of times, each time against a fresh tape. The mapping is released when the
+/// program is dropped.
+pub struct CompiledProgram {
+    mem: *mut libc::c_void,
+    len: usize,
+    func: extern "C" fn(memory: *mut u8, len: usize) -> i32,
+}
+
+impl CompiledProgram {
+    /// Run the program against the tape of `len` cells starting at `memory`, returning
+    /// the value the first cell ends up with, or the negated `errno` if reading input or
+    /// writing output failed, in which case the program stops there.
+    ///
+    /// # Safety
+    ///
+    /// `memory` must point to a writable tape of `len` cells. Unless the program was
+    /// compiled with bounds checks, the tape must also be large enough for every cell the
+    /// program visits. The tape should be zeroed for the program to behave like it would
+    /// under the interpreter.
+    pub unsafe fn call(&self, memory: *mut u8, len: usize) -> i32 {
+        (self.func)(memory, len)
+    }
+}
+
+impl Drop for CompiledProgram {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.mem, self.len);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use object::{
+    write::{Object, StandardSection, Symbol, SymbolSection},
+    Architecture, BinaryFormat, Endianness, SectionFlags, SectionKind, SymbolFlags, SymbolKind,
+    SymbolScope,
+};
+
+/// Name of the function the object file exports, as seen from C.
+pub const ENTRY_SYMBOL: &str = "bf_main";
+
+/// Wrap `code` in a relocatable object file for the host, with a global `bf_main`
+/// function symbol covering all of it.
+///
+/// The code has no relocations: loops branch relative to themselves and I/O goes through
+/// system calls, so it runs wherever the linker places it.
+pub fn write(code: &[u8]) -> Vec<u8> {
+    let format = if cfg!(target_os = "macos") {
+        BinaryFormat::MachO
+    } else {
+        BinaryFormat::Elf
+    };
+    let architecture = if cfg!(target_arch = "aarch64") {
+        Architecture::Aarch64
+    } else {
+        Architecture::X86_64
+    };
+    let mut object = Object::new(format, architecture, Endianness::Little);
+
+    let text = object.section_id(StandardSection::Text);
+    let offset = object.append_section_data(text, code, 16);
+    object.add_symbol(Symbol {
+        name: ENTRY_SYMBOL.as_bytes().to_vec(),
+        value: offset,
+        size: code.len() as u64,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Section(text),
+        flags: SymbolFlags::None,
+    });
+
+    // Without this note, GNU linkers assume the code needs an executable stack
+    if format == BinaryFormat::Elf {
+        let note = object.add_section(vec![], b".note.GNU-stack".to_vec(), SectionKind::Note);
+        object.section_mut(note).flags = SectionFlags::Elf { sh_flags: 0 };
+    }
+
+    object
+        .write()
+        .expect("an object with one section and symbol is always valid")
+}
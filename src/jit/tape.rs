@@ -0,0 +1,121 @@
+use std::{io, ptr};
+
+/// A zeroed tape for compiled programs, owning its cells on the heap.
+///
+/// Pass [`as_mut_ptr`](Self::as_mut_ptr) and [`len`](Self::len) to
+/// [`CompiledProgram::call`](super::CompiledProgram::call), then read back what the
+/// program left behind with [`cells`](Self::cells).
+pub struct Tape {
+    cells: Vec<u8>,
+}
+
+impl Tape {
+    /// Allocate a tape of `len` zeroed cells.
+    pub fn new(len: usize) -> Self {
+        Self {
+            cells: vec![0; len],
+        }
+    }
+
+    /// Pointer to the first cell, which compiled code writes through.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.cells.as_mut_ptr()
+    }
+
+    /// Number of cells.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Whether the tape has no cells.
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// The cells as the program left them.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+}
+
+/// A zeroed JIT tape surrounded by inaccessible guard pages.
+///
+/// Moving off either end of the tape and touching a cell faults with SIGSEGV (or SIGBUS)
+/// at the instruction that did it, instead of silently reading or corrupting other
+/// memory. That crash is the point: it is meant for debugging programs that run away.
+///
+/// The tape is rounded up to whole pages, so a program can use a few cells past the
+/// length it asked for before hitting the trailing guard page. Stepping left of the
+/// first cell always faults.
+pub struct GuardedTape {
+    /// Start of the whole mapping, including both guard pages
+    mem: *mut libc::c_void,
+    /// Size of the whole mapping
+    mapped: usize,
+    page_size: usize,
+}
+
+impl GuardedTape {
+    /// Map a tape of at least `len` cells between two guard pages.
+    pub fn new(len: usize) -> io::Result<Self> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let usable = len.max(1).div_ceil(page_size) * page_size;
+        let mapped = usable + 2 * page_size;
+
+        // Everything starts out inaccessible, then the tape between the guards is opened up
+        let mem = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mapped,
+                libc::PROT_NONE,
+                libc::MAP_ANON | libc::MAP_PRIVATE,
+                -1,
+                0,
+            )
+        };
+        if mem == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let tape = Self {
+            mem,
+            mapped,
+            page_size,
+        };
+        let result = unsafe {
+            libc::mprotect(
+                tape.as_mut_ptr() as *mut libc::c_void,
+                usable,
+                libc::PROT_READ | libc::PROT_WRITE,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(tape)
+    }
+
+    /// Pointer to the first cell.
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        unsafe { (self.mem as *mut u8).add(self.page_size) }
+    }
+
+    /// Number of usable cells, which is the requested length rounded up to whole pages.
+    pub fn len(&self) -> usize {
+        self.mapped - 2 * self.page_size
+    }
+
+    /// Whether the tape has no cells, which never happens.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for GuardedTape {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.mem, self.mapped);
+        }
+    }
+}
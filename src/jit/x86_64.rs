@@ -0,0 +1,387 @@
+use super::{CompileError, OUTPUT_BATCH};
+use crate::codegen::CodeGen;
+
+/// mov eax, 0x01 (write syscall)
+#[cfg(target_os = "linux")]
+const WRITE_SYSCALL: [u8; 5] = [0xB8, 0x01, 0x00, 0x00, 0x00];
+/// mov eax, 0x00 (read syscall)
+#[cfg(target_os = "linux")]
+const READ_SYSCALL: [u8; 5] = [0xB8, 0x00, 0x00, 0x00, 0x00];
+
+/// mov eax, 0x2000004 (write syscall, BSD class)
+#[cfg(target_os = "macos")]
+const WRITE_SYSCALL: [u8; 5] = [0xB8, 0x04, 0x00, 0x00, 0x02];
+/// mov eax, 0x2000003 (read syscall, BSD class)
+#[cfg(target_os = "macos")]
+const READ_SYSCALL: [u8; 5] = [0xB8, 0x03, 0x00, 0x00, 0x02];
+
+/// test rax, rax; js rel32 (Linux returns -errno when a syscall fails)
+#[cfg(target_os = "linux")]
+const JUMP_IF_FAILED: [u8; 5] = [0x48, 0x85, 0xC0, 0x0F, 0x88];
+/// test rax, rax; js rel32 (The flags from checking for EINTR are gone)
+#[cfg(target_os = "linux")]
+const JUMP_IF_READ_FAILED: [u8; 5] = JUMP_IF_FAILED;
+/// ret (RAX already holds -errno)
+#[cfg(target_os = "linux")]
+const IO_FAILED: [u8; 1] = [0xC3];
+
+/// cmp rax, -4 (Linux returns -errno when a syscall fails, so this compares with -EINTR)
+#[cfg(target_os = "linux")]
+const CHECK_INTERRUPTED: &[u8] = &[0x48, 0x83, 0xF8, 0xFC];
+
+/// jc rel32 (macOS sets the carry flag when a syscall fails)
+#[cfg(target_os = "macos")]
+const JUMP_IF_FAILED: [u8; 2] = [0x0F, 0x82];
+/// jmp rel32 (Only reached after a failed read, and comparing with EINTR lost the carry)
+#[cfg(target_os = "macos")]
+const JUMP_IF_READ_FAILED: [u8; 1] = [0xE9];
+/// neg eax; ret (RAX holds errno, which is returned negated like on Linux)
+#[cfg(target_os = "macos")]
+const IO_FAILED: [u8; 3] = [0xF7, 0xD8, 0xC3];
+
+/// jnc +10; cmp eax, 4 (macOS sets the carry flag when a syscall fails and returns errno
+/// in RAX, so this only compares with EINTR after a failure. A successful read skips the
+/// retry and the failure jump that follow)
+#[cfg(target_os = "macos")]
+const CHECK_INTERRUPTED: &[u8] = &[0x73, 0x0A, 0x83, 0xF8, 0x04];
+
+/// Register numbers used in ModRM bytes.
+const RCX: u8 = 1;
+const RDI: u8 = 7;
+
+/// x86-64 code generator. The data pointer lives in RDI, which is also the first argument.
+///
+/// `syscall` only clobbers RAX, RCX and R11, so the data pointer is parked in RSI
+/// (the buffer argument of read/write) across I/O.
+///
+/// The start of the tape is kept in R9 so the value of the first cell can be returned.
+/// With bounds checks the tape length comes in RSI, and the tape is kept as the range
+/// `[R9, R10)` since RSI is used as scratch.
+///
+/// A failed `write`, e.g. once the reader of a pipe is gone, returns -errno right away
+/// instead of carrying on with nowhere for the output to go, and so does a failed `read`
+/// rather than carrying on with a stale cell. A `read` that fails with `EINTR` is
+/// retried. Failures show in RAX as -errno on Linux, and on macOS as the carry flag with
+/// errno in RAX.
+pub struct X86_64 {
+    code: Vec<u8>,
+    /// Offsets just past the JE emitted for each `[` or if still waiting for its end
+    loops: Vec<usize>,
+    /// Offsets just past the jump emitted after each `read` and `write`, patched by
+    /// `finish`
+    io_checks: Vec<usize>,
+    bounds_checks: bool,
+    /// The first operand or branch that didn't fit, returned by `finish`
+    error: Option<CompileError>,
+}
+
+impl X86_64 {
+    pub fn new(bounds_checks: bool) -> Self {
+        // mov r9, rdi (Start of the tape)
+        let mut code = vec![0x49, 0x89, 0xF9];
+        if bounds_checks {
+            // lea r10, [rdi + rsi] (End of the tape)
+            code.extend_from_slice(&[0x4C, 0x8D, 0x14, 0x37]);
+        }
+
+        Self {
+            code,
+            loops: vec![],
+            io_checks: vec![],
+            bounds_checks,
+            error: None,
+        }
+    }
+
+    /// Trap with `ud2` unless the address in the register numbered `reg` (RDI or RCX) is
+    /// on the tape.
+    fn check_bounds(&mut self, reg: u8) {
+        if !self.bounds_checks {
+            return;
+        }
+
+        self.code.extend_from_slice(&[
+            0x4C,
+            0x39,
+            0xC8 | reg, // cmp reg, r9
+            0x72,
+            0x05, // jb <ud2>
+            0x4C,
+            0x39,
+            0xD0 | reg, // cmp reg, r10
+            0x72,
+            0x02, // jb <after ud2>
+            0x0F,
+            0x0B, // ud2
+        ]);
+    }
+
+    /// Jump to the I/O failure exit if the last syscall failed. Only MOVs and LEAs may
+    /// come between the syscall and this check, so the flags survive on macOS.
+    fn check_write(&mut self) {
+        self.check_io(&JUMP_IF_FAILED);
+    }
+
+    /// Jump to the I/O failure exit after a `read` that failed with something other than
+    /// `EINTR`.
+    fn check_read(&mut self) {
+        self.check_io(&JUMP_IF_READ_FAILED);
+    }
+
+    fn check_io(&mut self, jump: &[u8]) {
+        self.code.extend_from_slice(jump);
+        self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        self.io_checks.push(self.code.len());
+    }
+
+    /// Set the rel32 ending at `end` so the jump lands on `target`.
+    fn patch_jump(&mut self, end: usize, target: usize) {
+        match i32::try_from(target as isize - end as isize) {
+            Ok(displacement) => {
+                self.code[end - 4..end].copy_from_slice(&displacement.to_le_bytes())
+            }
+            Err(_) => self.fail(CompileError::BranchOutOfRange),
+        }
+    }
+
+    /// `offset` as a 32-bit displacement, or 0 with an error for `finish` if it doesn't
+    /// fit.
+    fn displacement(&mut self, offset: isize) -> i32 {
+        i32::try_from(offset).unwrap_or_else(|_| {
+            self.fail(CompileError::OffsetOutOfRange);
+            0
+        })
+    }
+
+    /// Keep `error` for `finish` to return, unless an earlier one is already kept.
+    fn fail(&mut self, error: CompileError) {
+        self.error.get_or_insert(error);
+    }
+}
+
+impl CodeGen for X86_64 {
+    type Output = Result<Vec<u8>, CompileError>;
+
+    fn inc(&mut self) {
+        // inc byte [rdi]
+        self.code.extend_from_slice(&[0xFE, 0x07]);
+    }
+
+    fn dec(&mut self) {
+        // dec byte [rdi]
+        self.code.extend_from_slice(&[0xFE, 0x0F]);
+    }
+
+    fn move_left(&mut self) {
+        // dec rdi
+        self.code.extend_from_slice(&[0x48, 0xFF, 0xCF]);
+        self.check_bounds(RDI);
+    }
+
+    fn move_right(&mut self) {
+        // inc rdi
+        self.code.extend_from_slice(&[0x48, 0xFF, 0xC7]);
+        self.check_bounds(RDI);
+    }
+
+    fn output(&mut self) {
+        self.code.extend_from_slice(&[
+            0x48, 0x89, 0xFE, // mov rsi, rdi (Data pointer)
+            0xBF, 0x01, 0x00, 0x00, 0x00, // mov edi, 0x01 (STD OUT)
+            0xBA, 0x01, 0x00, 0x00, 0x00, // mov edx, 0x01 (Always 1 byte output)
+        ]);
+        self.code.extend_from_slice(&WRITE_SYSCALL);
+        self.code.extend_from_slice(&[
+            0x0F, 0x05, // syscall
+            0x48, 0x89, 0xF7, // mov rdi, rsi (Restore data pointer)
+        ]);
+        self.check_write();
+    }
+
+    fn output_repeat(&mut self, count: usize) {
+        let mut remaining = count;
+        while remaining > 0 {
+            let batch = remaining.min(OUTPUT_BATCH) as u32;
+            // Keep the stack 16-byte aligned
+            let reserved = (batch + 15) & !15;
+
+            // Fill a stack buffer with copies of the cell and write it in one syscall
+            self.code.extend_from_slice(&[
+                0x0F, 0xB6, 0x07, // movzx eax, byte [rdi] (Byte to repeat)
+                0x49, 0x89, 0xF8, // mov r8, rdi (Save data pointer)
+                0x48, 0x81, 0xEC, // sub rsp, reserved
+            ]);
+            self.code.extend_from_slice(&reserved.to_le_bytes());
+            self.code.extend_from_slice(&[
+                0x48, 0x89, 0xE7, // mov rdi, rsp
+                0xB9, // mov ecx, batch
+            ]);
+            self.code.extend_from_slice(&batch.to_le_bytes());
+            self.code.extend_from_slice(&[
+                0xF3, 0xAA, // rep stosb
+                0x48, 0x89, 0xE6, // mov rsi, rsp (Buffer)
+                0xBF, 0x01, 0x00, 0x00, 0x00, // mov edi, 0x01 (STD OUT)
+                0xBA, // mov edx, batch
+            ]);
+            self.code.extend_from_slice(&batch.to_le_bytes());
+            self.code.extend_from_slice(&WRITE_SYSCALL);
+            self.code.extend_from_slice(&[
+                0x0F, 0x05, // syscall
+                0x48, 0x8D, 0xA4, 0x24, // lea rsp, [rsp + reserved] (Leaves the flags alone)
+            ]);
+            self.code.extend_from_slice(&reserved.to_le_bytes());
+            self.code.extend_from_slice(&[
+                0x4C, 0x89, 0xC7, // mov rdi, r8 (Restore data pointer)
+            ]);
+            self.check_write();
+
+            remaining -= batch as usize;
+        }
+    }
+
+    fn input(&mut self) {
+        self.code.extend_from_slice(&[
+            0x48, 0x89, 0xFE, // mov rsi, rdi (Data pointer)
+            0x31, 0xFF, // xor edi, edi (STD IN)
+            0xBA, 0x01, 0x00, 0x00, 0x00, // mov edx, 0x01 (Always 1 byte input)
+        ]);
+        // A read interrupted by a signal before it got any input is simply issued again.
+        // Only RAX is reloaded: `syscall` leaves RDI, RSI and RDX alone
+        let retry = self.code.len();
+        self.code.extend_from_slice(&READ_SYSCALL);
+        self.code.extend_from_slice(&[0x0F, 0x05]); // syscall
+        self.code.extend_from_slice(CHECK_INTERRUPTED);
+        let displacement = retry as isize - (self.code.len() + 2) as isize;
+        // je retry
+        self.code
+            .extend_from_slice(&[0x74, displacement as i8 as u8]);
+        self.check_read();
+        self.code.extend_from_slice(&[
+            0x48, 0x85, 0xC0, // test rax, rax (Bytes read)
+            0x75, 0x03, // jnz +3
+            0xC6, 0x06, 0x00, // mov byte [rsi], 0 (EOF)
+            0x48, 0x89, 0xF7, // mov rdi, rsi (Restore data pointer)
+        ]);
+    }
+
+    fn add(&mut self, delta: i8) {
+        // add byte [rdi], imm8
+        self.code.extend_from_slice(&[0x80, 0x07, delta as u8]);
+    }
+
+    fn move_pointer(&mut self, delta: isize) {
+        let delta = self.displacement(delta);
+
+        // add rdi, imm32
+        self.code.extend_from_slice(&[0x48, 0x81, 0xC7]);
+        self.code.extend_from_slice(&delta.to_le_bytes());
+        self.check_bounds(RDI);
+    }
+
+    fn set_zero(&mut self) {
+        // mov byte [rdi], 0
+        self.code.extend_from_slice(&[0xC6, 0x07, 0x00]);
+    }
+
+    fn set_const(&mut self, value: i8) {
+        // mov byte [rdi], imm8
+        self.code.extend_from_slice(&[0xC6, 0x07, value as u8]);
+    }
+
+    fn add_mul(&mut self, offset: isize, factor: i8) {
+        let offset = self.displacement(offset);
+
+        if self.bounds_checks {
+            // lea rcx, [rdi + offset]
+            self.code.extend_from_slice(&[0x48, 0x8D, 0x8F]);
+            self.code.extend_from_slice(&offset.to_le_bytes());
+            self.check_bounds(RCX);
+        }
+
+        self.code.extend_from_slice(&[
+            0x0F,
+            0xB6,
+            0x07, // movzx eax, byte [rdi]
+            0x6B,
+            0xC0,
+            factor as u8, // imul eax, eax, factor
+            0x00,
+            0x87, // add byte [rdi + offset], al
+        ]);
+        self.code.extend_from_slice(&offset.to_le_bytes());
+    }
+
+    fn add_at_offset(&mut self, offset: isize, delta: i8) {
+        let offset = self.displacement(offset);
+
+        if self.bounds_checks {
+            // lea rcx, [rdi + offset]
+            self.code.extend_from_slice(&[0x48, 0x8D, 0x8F]);
+            self.code.extend_from_slice(&offset.to_le_bytes());
+            self.check_bounds(RCX);
+        }
+
+        // add byte [rdi + offset], imm8
+        self.code.extend_from_slice(&[0x80, 0x87]);
+        self.code.extend_from_slice(&offset.to_le_bytes());
+        self.code.push(delta as u8);
+    }
+
+    fn loop_start(&mut self) {
+        self.code.extend_from_slice(&[
+            0x80, 0x3F, 0x00, // cmp byte [rdi], 0
+            0x0F, 0x84, 0x00, 0x00, 0x00, 0x00, // je <end> (patched by loop_end)
+        ]);
+        self.loops.push(self.code.len());
+    }
+
+    fn loop_end(&mut self) {
+        let start = self.loops.pop().expect("unbalanced loop");
+
+        self.code.extend_from_slice(&[
+            0x80, 0x3F, 0x00, // cmp byte [rdi], 0
+            0x0F, 0x85, 0x00, 0x00, 0x00, 0x00, // jne <body>
+        ]);
+        let end = self.code.len();
+
+        self.patch_jump(end, start);
+        self.patch_jump(start, end);
+    }
+
+    fn if_nonzero(&mut self) {
+        self.code.extend_from_slice(&[
+            0x80, 0x3F, 0x00, // cmp byte [rdi], 0
+            0x0F, 0x84, 0x00, 0x00, 0x00, 0x00, // je <end> (patched by end_if)
+        ]);
+        self.loops.push(self.code.len());
+    }
+
+    fn end_if(&mut self) {
+        let start = self.loops.pop().expect("unbalanced if");
+        self.patch_jump(start, self.code.len());
+    }
+
+    fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    fn finish(mut self) -> Self::Output {
+        self.code.extend_from_slice(&[
+            0x41, 0x0F, 0xB6, 0x01, // movzx eax, byte [r9] (Return the first cell)
+            0xC3, // ret
+        ]);
+
+        if !self.io_checks.is_empty() {
+            let failed = self.code.len();
+            self.code.extend_from_slice(&IO_FAILED);
+            for end in std::mem::take(&mut self.io_checks) {
+                self.patch_jump(end, failed);
+            }
+        }
+
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.code),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! A Brainfuck interpreter and JIT compiler.
+//!
+//! Every backend runs programs against a tape of cells that all start at zero. Cells are
+//! 8 bits wide unless the interpreter is given a wider [`Cell`] type.
+
+mod aarch64;
+mod bytecode;
+mod cell;
+mod codegen;
+mod emit;
+mod filter;
+mod interpreter;
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+mod jit;
+mod op;
+mod optimizer;
+mod parser;
+mod stats;
+
+/// Default maximum number of cells on the tape, shared by every backend.
+pub const DEFAULT_TAPE_SIZE: usize = 30_000;
+
+/// Whether the JIT backend is available: it needs the `jit` feature, which is on by
+/// default, and a target it supports.
+pub const JIT_SUPPORTED: bool = cfg!(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+));
+
+pub use bytecode::{emit_bytecode, load_bytecode, BytecodeError, BYTECODE_VERSION};
+pub use cell::Cell;
+pub use emit::{emit_asm, emit_c, emit_rust, emit_wasm};
+pub use filter::{ByteFilter, FilterReader, FilterWriter};
+pub use interpreter::{
+    run_to_string, EofPolicy, ExecutionError, Interpreter, OutputEvent, OutputMode, PointerMode,
+    VmState,
+};
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+pub use jit::{CompileError, CompiledProgram, GuardedTape, JitCompiler, Tape};
+pub use op::{dump_ops, lower, nest, Op};
+pub use optimizer::{optimize, optimize_with_level, optimize_with_positions, OptLevel};
+pub use parser::{
+    parse, parse_extended, parse_tree, parse_with_positions, Location, ParseError,
+    DEFAULT_MAX_LOOP_DEPTH,
+};
+pub use stats::{LoopProfile, ProgramStats, RunStats};
@@ -1,249 +1,1278 @@
 use std::{
+    collections::BTreeSet,
     env,
-    io::{Error, Read, Write},
-    ptr,
+    fs::File,
+    io::{self, BufReader, IsTerminal, Read, Write},
+    process::ExitCode,
+    time::Duration,
 };
 
-use libc::pthread_jit_write_protect_np;
-
-#[derive(Debug)]
-enum Op {
-    /// +
-    /// Increment the data pointer by one (to point to the next cell to the right).
-    Inc,
-    /// -
-    /// Decrement the data pointer by one (to point to the next cell to the left).
-    Dec,
-    /// <
-    /// Increment the byte at the data pointer by one.
-    Left,
-    /// >
-    /// Decrement the byte at the data pointer by one.
-    Right,
-    /// .
-    /// Output the byte at the data pointer.
-    Output,
-    /// ,
-    /// Accept one byte of input, storing its value in the byte at the data pointer.
-    Input,
-    /// [
-    /// If the byte at the data pointer is zero, then instead of moving the instruction pointer forward to the next command,
-    /// jump it forward to the command after the matching ] command.
-    JumpIfZero(usize),
-    /// ]
-    /// If the byte at the data pointer is nonzero, then instead of moving the instruction pointer forward to the next command,
-    /// jump it back to the command after the matching [ command.[a]
-    JumpIfNonZero(usize),
-}
-
-fn main() -> Result<(), &'static str> {
-    let args: Vec<String> = env::args().collect();
-    let file_path = &args[1];
-
-    let program = std::fs::read_to_string(file_path).unwrap();
-
-    let mut operations = vec![];
-    let mut jump_op_stack = vec![];
-
-    for (i, char) in program.chars().enumerate() {
-        match char {
-            '+' => operations.push(Op::Inc),
-            '-' => operations.push(Op::Dec),
-            '<' => operations.push(Op::Left),
-            '>' => operations.push(Op::Right),
-            '.' => operations.push(Op::Output),
-            ',' => operations.push(Op::Input),
-            '[' => {
-                operations.push(Op::JumpIfZero(0));
-                jump_op_stack.push(i);
-            }
-            ']' => {
-                match jump_op_stack.pop() {
-                    Some(addr) => {
-                        operations.push(Op::JumpIfNonZero(addr + 1));
-
-                        // Back patch the matching `[`
-                        match operations[addr] {
-                            Op::JumpIfZero(ref mut addr) => *addr = i + 1,
-                            _ => unreachable!(),
-                        };
-                    }
-                    None => return Err("Unbalanced jumps"),
-                }
-            }
-            _ => {
-                // Brainfuck ignores all other chars
-            }
-        }
-    }
+use brainphoque::{
+    dump_ops, emit_asm, emit_bytecode, emit_c, emit_rust, emit_wasm, load_bytecode,
+    optimize_with_positions, parse_with_positions, ByteFilter, Cell, ExecutionError, FilterReader,
+    FilterWriter, Interpreter, Location, Op, OptLevel, OutputMode, ParseError, PointerMode,
+    ProgramStats, RunStats, DEFAULT_MAX_LOOP_DEPTH, DEFAULT_TAPE_SIZE, JIT_SUPPORTED,
+};
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+use brainphoque::{CompileError, GuardedTape, JitCompiler, Tape};
 
-    // let mut interpreter = Interpreter::new(operations, std::io::stdin(), std::io::stdout());
-    // interpreter.run();
+/// Width of the interpreter's tape cells
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CellBits {
+    U8,
+    U16,
+    U32,
+}
 
-    let mut jit_compiler = JitCompiler::new(operations);
-    let jit_memory = [65; 1024];
-    let mmr_addr = jit_memory.as_ptr();
-    let func = jit_compiler.compile();
-    func(mmr_addr);
+/// Source language to translate the program into instead of running it
+enum Emit {
+    Asm,
+    C,
+    Rust,
+    Wasm,
+    /// Native object file, written to the given path
+    Object(String),
+    /// Optimized ops as bytecode, written to the given path
+    Bytecode(String),
+}
 
-    Ok(())
+/// Backend that executes the program
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Interpret,
+    Jit,
 }
 
-struct Interpreter<R, W> {
-    ops: Vec<Op>,
-    cells: [u8; 1000],
-    reader: R,
-    writer: W,
+/// Where `,` reads the program's input from
+enum Input {
+    Stdin,
+    File(String),
+    /// Input given inline with `--input-string`
+    String(String),
 }
 
-impl<R, W> Interpreter<R, W>
-where
-    R: Read,
-    W: Write,
-{
-    fn new(ops: Vec<Op>, reader: R, writer: W) -> Self {
-        Self {
-            ops,
-            cells: [0; 1000],
-            writer,
-            reader,
-        }
-    }
+/// Where the Brainfuck source comes from
+enum Program {
+    /// Files joined in the order given
+    Files(Vec<String>),
+    Stdin,
+    /// Source given inline with `-e`
+    Eval(String),
+    /// Already optimized ops, loaded from a bytecode file
+    Bytecode(String),
+}
 
-    fn run(&mut self) {
-        let mut ip = 0;
-        let mut dp = 0;
+struct Options {
+    program: Program,
+    input: Input,
+    /// Number of cells on the tape
+    cells: usize,
+    /// What happens when the data pointer leaves the tape
+    pointer: PointerMode,
+    /// Cell the data pointer starts on
+    start_offset: usize,
+    cell_bits: CellBits,
+    /// How `.` writes a cell
+    output_mode: OutputMode,
+    /// Translations applied to the program's input, in order
+    input_filters: Vec<ByteFilter>,
+    /// Translations applied to the program's output, in order
+    output_filters: Vec<ByteFilter>,
+    /// Maximum number of ops to execute
+    max_steps: Option<u64>,
+    /// Deepest loop nesting to accept
+    max_depth: usize,
+    /// Maximum time to run for
+    timeout: Option<Duration>,
+    /// Print execution statistics after running
+    stats: bool,
+    /// Print the loops that executed the most ops after running
+    profile: bool,
+    /// Print the loops that iterated the most after running
+    hot_loops: bool,
+    /// Print the tape after running
+    dump_tape: bool,
+    /// Log each executed op to stderr
+    trace: bool,
+    /// Maximum number of ops to log when tracing
+    trace_limit: Option<u64>,
+    /// Step through the program in the interactive debugger
+    debug: bool,
+    /// Read and run lines of source one at a time on the same tape
+    repl: bool,
+    /// Recognize extension commands such as `#`
+    extensions: bool,
+    /// Reject bytes that are neither commands nor whitespace
+    strict: bool,
+    /// Optimization passes to run before execution
+    opt_level: OptLevel,
+    emit: Option<Emit>,
+    /// Print the optimized ops before running
+    dump_ops: bool,
+    /// Stop after printing diagnostics instead of running the program
+    no_run: bool,
+    /// Print static counts for the program instead of running it
+    count_only: bool,
+    /// Exit with the value of the first cell after running
+    exit_with_cell: bool,
+    /// Print the JIT's machine code for each op before running
+    dump_asm: bool,
+    /// File to write the JIT's machine code to before it's mapped
+    dump_machine_code: Option<String>,
+    /// Trap in JIT code when the data pointer leaves the tape
+    safe_jit: bool,
+    /// Put guard pages around the JIT tape so overruns fault
+    guarded_tape: bool,
+    /// Backend chosen with `--mode`, if any
+    mode: Option<Mode>,
+    /// Print the help text and exit
+    help: bool,
+    /// Print the version and exit
+    version: bool,
+}
 
-        while ip < self.ops.len() {
-            match self.ops[ip] {
-                Op::Inc => {
-                    self.cells[dp] += 1;
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut program = None;
+        let mut input = Input::Stdin;
+        let mut cells = DEFAULT_TAPE_SIZE;
+        let mut pointer = PointerMode::Strict;
+        let mut start_offset = 0;
+        let mut cell_bits = CellBits::U8;
+        let mut output_mode = OutputMode::Raw;
+        let mut input_filters = vec![];
+        let mut output_filters = vec![];
+        let mut max_steps = None;
+        let mut max_depth = DEFAULT_MAX_LOOP_DEPTH;
+        let mut timeout = None;
+        let mut stats = false;
+        let mut profile = false;
+        let mut hot_loops = false;
+        let mut dump_tape = false;
+        let mut trace = false;
+        let mut trace_limit = None;
+        let mut debug = false;
+        let mut repl = false;
+        let mut extensions = false;
+        let mut strict = false;
+        let mut opt_level = OptLevel::default();
+        let mut emit = None;
+        let mut dump_ops = false;
+        let mut no_run = false;
+        let mut count_only = false;
+        let mut exit_with_cell = false;
+        let mut dump_asm = false;
+        let mut dump_machine_code = None;
+        let mut safe_jit = false;
+        let mut guarded_tape = false;
+        let mut mode = None;
+        let mut help = false;
+        let mut version = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "--cells" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    cells = match value.parse() {
+                        Ok(cells) if cells > 0 => cells,
+                        _ => {
+                            return Err(format!(
+                                "{} must be a positive number, got '{}'",
+                                arg, value
+                            ))
+                        }
+                    };
                 }
-                Op::Dec => {
-                    self.cells[dp] -= 1;
+                "--bidirectional" => pointer = PointerMode::Grow,
+                "--pointer" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    pointer = match value.as_str() {
+                        "strict" => PointerMode::Strict,
+                        "grow" => PointerMode::Grow,
+                        "wrap" => PointerMode::Wrap,
+                        _ => {
+                            return Err(format!(
+                                "{} must be strict, grow or wrap, got '{}'",
+                                arg, value
+                            ))
+                        }
+                    };
                 }
-                Op::Left => {
-                    if dp > 0 {
-                        dp -= 1;
-                    } else {
-                        panic!("Tried to move left when dp was 0");
-                    }
+                "--start-offset" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    start_offset = match value.parse() {
+                        Ok(offset) => offset,
+                        _ => return Err(format!("{} must be a number, got '{}'", arg, value)),
+                    };
                 }
-                Op::Right => {
-                    dp += 1;
+                "--cell-bits" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    cell_bits = match value.as_str() {
+                        "8" => CellBits::U8,
+                        "16" => CellBits::U16,
+                        "32" => CellBits::U32,
+                        _ => return Err(format!("{} must be 8, 16 or 32, got '{}'", arg, value)),
+                    };
                 }
-                Op::Output => {
-                    self.writer.write(&[self.cells[dp]]).unwrap();
+                "--output-mode" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    output_mode = match value.as_str() {
+                        "raw" => OutputMode::Raw,
+                        "decimal" => OutputMode::Decimal,
+                        _ => {
+                            return Err(format!("{} must be raw or decimal, got '{}'", arg, value))
+                        }
+                    };
                 }
-                Op::Input => {
-                    let mut read = [0; 1];
-                    self.reader.read(&mut read).unwrap();
-                    self.cells[dp] = read[0];
+                "--input-filter" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    input_filters.push(byte_filter(&arg, &value)?);
                 }
-                Op::JumpIfZero(addr) => {
-                    if self.cells[dp] == 0 {
-                        ip = addr;
-                        continue;
-                    }
+                "--output-filter" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    output_filters.push(byte_filter(&arg, &value)?);
                 }
-                Op::JumpIfNonZero(addr) => {
-                    if self.cells[dp] != 0 {
-                        ip = addr;
-                        continue;
-                    }
+                "--max-steps" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    max_steps = match value.parse() {
+                        Ok(steps) => Some(steps),
+                        _ => return Err(format!("{} must be a number, got '{}'", arg, value)),
+                    };
+                }
+                "--max-depth" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    max_depth = match value.parse() {
+                        Ok(depth) => depth,
+                        _ => return Err(format!("{} must be a number, got '{}'", arg, value)),
+                    };
+                }
+                "--timeout" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    timeout = match value.parse() {
+                        Ok(ms) => Some(Duration::from_millis(ms)),
+                        _ => {
+                            return Err(format!(
+                                "{} must be a number of milliseconds, got '{}'",
+                                arg, value
+                            ))
+                        }
+                    };
+                }
+                "--stats" => stats = true,
+                "--dump-tape" => dump_tape = true,
+                "--profile" => profile = true,
+                "--hot-loops" => hot_loops = true,
+                "--trace" => trace = true,
+                "--trace-limit" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    trace_limit = match value.parse() {
+                        Ok(limit) => Some(limit),
+                        _ => return Err(format!("{} must be a number, got '{}'", arg, value)),
+                    };
+                    trace = true;
+                }
+                "--debug" => debug = true,
+                "--repl" => repl = true,
+                "--extensions" => extensions = true,
+                "--strict" => strict = true,
+                "-O0" => opt_level = OptLevel::O0,
+                "-O1" => opt_level = OptLevel::O1,
+                "-O2" => opt_level = OptLevel::O2,
+                "--emit" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    emit = match value.as_str() {
+                        "asm" => Some(Emit::Asm),
+                        "c" => Some(Emit::C),
+                        "rust" => Some(Emit::Rust),
+                        "wasm" => Some(Emit::Wasm),
+                        "obj" => Some(Emit::Object(
+                            args.next()
+                                .ok_or(format!("{} obj requires an output file", arg))?,
+                        )),
+                        "bytecode" => Some(Emit::Bytecode(
+                            args.next()
+                                .ok_or(format!("{} bytecode requires an output file", arg))?,
+                        )),
+                        _ => {
+                            return Err(format!(
+                                "{} must be asm, c, rust, wasm, obj or bytecode, got '{}'",
+                                arg, value
+                            ))
+                        }
+                    };
+                }
+                "--dump-ops" => dump_ops = true,
+                "--no-run" => no_run = true,
+                "--count-only" => count_only = true,
+                "--exit-with-cell" => exit_with_cell = true,
+                "--dump-asm" => dump_asm = true,
+                "--dump-machine-code" => {
+                    dump_machine_code =
+                        Some(args.next().ok_or(format!("{} requires a value", arg))?);
+                }
+                "--safe-jit" => safe_jit = true,
+                "--guarded-tape" => guarded_tape = true,
+                "--input" => {
+                    let path = args.next().ok_or(format!("{} requires a value", arg))?;
+                    set_input(&mut input, Input::File(path))?;
+                }
+                "--input-string" => {
+                    let text = args.next().ok_or(format!("{} requires a value", arg))?;
+                    set_input(&mut input, Input::String(text))?;
+                }
+                "--mode" => {
+                    let value = args.next().ok_or(format!("{} requires a value", arg))?;
+                    mode = match value.as_str() {
+                        "interpret" => Some(Mode::Interpret),
+                        "jit" => Some(Mode::Jit),
+                        _ => {
+                            return Err(format!(
+                                "{} must be interpret or jit, got '{}'",
+                                arg, value
+                            ))
+                        }
+                    };
                 }
+                "-e" | "--eval" => {
+                    let source = args.next().ok_or(format!("{} requires a value", arg))?;
+                    set_program(&mut program, Program::Eval(source))?;
+                }
+                "--run-bytecode" => {
+                    let path = args.next().ok_or(format!("{} requires a value", arg))?;
+                    set_program(&mut program, Program::Bytecode(path))?;
+                }
+                "-h" | "--help" => help = true,
+                "-V" | "--version" => version = true,
+                "-" => set_program(&mut program, Program::Stdin)?,
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+                _ => set_program(&mut program, Program::Files(vec![arg]))?,
+            }
+        }
+
+        if start_offset >= cells {
+            return Err(format!(
+                "--start-offset must be less than the {} cells on the tape, got {}",
+                cells, start_offset
+            ));
+        }
+
+        Ok(Self {
+            program: program.unwrap_or(Program::Stdin),
+            input,
+            cells,
+            pointer,
+            start_offset,
+            cell_bits,
+            output_mode,
+            input_filters,
+            output_filters,
+            max_steps,
+            max_depth,
+            timeout,
+            stats,
+            profile,
+            hot_loops,
+            dump_tape,
+            trace,
+            trace_limit,
+            debug,
+            repl,
+            extensions,
+            strict,
+            opt_level,
+            emit,
+            dump_ops,
+            no_run,
+            count_only,
+            exit_with_cell,
+            dump_asm,
+            dump_machine_code,
+            safe_jit,
+            guarded_tape,
+            mode,
+            help,
+            version,
+        })
+    }
+
+    /// The first requested option that only the interpreter supports
+    fn interpreter_only_option(&self) -> Option<&'static str> {
+        // The JIT tape is a fixed buffer of bytes that can't grow to the left,
+        // compiled code runs to completion without counting anything, and it reads
+        // straight from the stdin file descriptor
+        if self.pointer != PointerMode::Strict {
+            Some("--pointer")
+        } else if self.start_offset != 0 {
+            Some("--start-offset")
+        } else if self.cell_bits != CellBits::U8 {
+            Some("--cell-bits")
+        } else if self.output_mode != OutputMode::Raw {
+            Some("--output-mode")
+        } else if !self.input_filters.is_empty() {
+            Some("--input-filter")
+        } else if !self.output_filters.is_empty() {
+            Some("--output-filter")
+        } else if self.max_steps.is_some() {
+            Some("--max-steps")
+        } else if self.timeout.is_some() {
+            Some("--timeout")
+        } else if self.stats {
+            Some("--stats")
+        } else if self.profile {
+            Some("--profile")
+        } else if self.hot_loops {
+            Some("--hot-loops")
+        } else if self.dump_tape {
+            Some("--dump-tape")
+        } else if self.trace {
+            Some("--trace")
+        } else if self.debug {
+            Some("--debug")
+        } else if self.repl {
+            Some("--repl")
+        } else if self.extensions {
+            Some("--extensions")
+        } else if matches!(self.input, Input::String(_)) {
+            Some("--input-string")
+        } else {
+            None
+        }
+    }
+
+    /// The first requested option that only the JIT supports
+    fn jit_only_option(&self) -> Option<&'static str> {
+        if self.dump_asm {
+            Some("--dump-asm")
+        } else if self.dump_machine_code.is_some() {
+            Some("--dump-machine-code")
+        } else if self.safe_jit {
+            Some("--safe-jit")
+        } else if self.guarded_tape {
+            Some("--guarded-tape")
+        } else {
+            None
+        }
+    }
+
+    /// The backend to run with, defaulting to the JIT when it's supported and can honor
+    /// every other option.
+    fn mode(&self) -> Result<Mode, String> {
+        let mode = match (self.mode, self.interpreter_only_option()) {
+            (Some(Mode::Jit), Some(option)) => {
+                return Err(format!("{} requires --mode interpret", option))
             }
+            (Some(mode), _) => mode,
+            (None, None) if JIT_SUPPORTED => Mode::Jit,
+            (None, _) => Mode::Interpret,
+        };
 
-            ip += 1;
+        if let (Mode::Interpret, Some(jit_option)) = (mode, self.jit_only_option()) {
+            return Err(match self.interpreter_only_option() {
+                Some(option) => format!("{} can't be combined with {}", jit_option, option),
+                None => format!("{} requires the JIT", jit_option),
+            });
         }
+
+        Ok(mode)
     }
 }
 
-struct JitCompiler {
-    ops: Vec<Op>,
+/// Record where the program's input comes from, rejecting a second source.
+fn set_input(input: &mut Input, source: Input) -> Result<(), String> {
+    if !matches!(input, Input::Stdin) {
+        return Err("only one of --input or --input-string may be given".to_string());
+    }
+
+    *input = source;
+    Ok(())
 }
 
-impl JitCompiler {
-    fn new(ops: Vec<Op>) -> Self {
-        Self { ops }
+/// Record where the program comes from, adding to the files already given but rejecting
+/// any other second source.
+fn set_program(program: &mut Option<Program>, source: Program) -> Result<(), String> {
+    match (program.as_mut(), source) {
+        (Some(Program::Files(files)), Program::Files(more)) => {
+            files.extend(more);
+            return Ok(());
+        }
+        (Some(_), _) => {
+            return Err("only program files, -, -e or --run-bytecode may be given".to_string());
+        }
+        (None, source) => *program = Some(source),
     }
 
-    fn compile(&mut self) -> extern "C" fn(memory: *const u8) {
-        let mut code: Vec<u8> = vec![];
+    Ok(())
+}
 
-        for op in &self.ops {
-            match op {
-                Op::Inc => {
-                    // LDRB W1, [X0]    ; Load the byte at the memory address pointed to by X0 into W1
-                    // ADD W1, W1, #1   ; Add 1 to the value in W1
-                    // STRB W1, [X0]    ; Store the modified byte back to the memory address in X0
-                    code.extend_from_slice(&[
-                        0x01, 0x00, 0x40, 0x39, 0x21, 0x04, 0x00, 0x11, 0x01, 0x00, 0x00, 0x39,
-                    ]);
-                }
-                Op::Dec => {
-                    // LDRB W1, [X0]    ; Load the byte at the memory address pointed to by X0 into W1
-                    // SUB W1, W1, #1   ; Add 1 to the value in W1
-                    // STRB W1, [X0]    ; Store the modified byte back to the memory address in X0
-                    code.extend_from_slice(&[
-                        0x01, 0x00, 0x40, 0x39, 0x21, 0x04, 0x00, 0x51, 0x01, 0x00, 0x00, 0x39,
-                    ]);
-                }
-                Op::Left => {
-                    // SUB X0, X0, #1
-                    code.extend_from_slice(&[0x00, 0x04, 0x00, 0xD1]);
-                }
-                Op::Right => {
-                    // ADD X0, X0, #1
-                    code.extend_from_slice(&[0x00, 0x04, 0x00, 0x91]);
-                }
-                Op::Output => {
-                    code.extend_from_slice(&[
-                        0xE3, 0x03, 0x00, 0xAA, // mov x3, x0 (Save x0, our data pointer)
-                        0x20, 0x00, 0x80, 0xD2, // movz x0, #0x01 (STD OUT)
-                        0xE1, 0x03, 0x03, 0xAA, // mov x1, x3 (Data pointer)
-                        0x22, 0x00, 0x80, 0xD2, // movz x2, #0x1 (Always 1 byte output)
-                        0x90, 0x00, 0x80, 0xD2, // movz x16, #0x04 (write syscall)
-                        0x01, 0x00, 0x00, 0xD4, // svc #0
-                        0xE0, 0x03, 0x03, 0xAA, // mov x0 x3 (Restore data pointer)
-                    ]);
+/// The filter named `value` given to the option `arg`.
+fn byte_filter(arg: &str, value: &str) -> Result<ByteFilter, String> {
+    match value {
+        "crlf-to-lf" => Ok(ByteFilter::CrlfToLf),
+        "lf-to-crlf" => Ok(ByteFilter::LfToCrlf),
+        "uppercase" => Ok(ByteFilter::Uppercase),
+        "lowercase" => Ok(ByteFilter::Lowercase),
+        _ => Err(format!(
+            "{} must be crlf-to-lf, lf-to-crlf, uppercase or lowercase, got '{}'",
+            arg, value
+        )),
+    }
+}
+
+/// The program's source, with where each file it was joined from starts.
+struct Source {
+    bytes: Vec<u8>,
+    /// Name and start offset of each file, in order
+    files: Vec<(String, usize)>,
+}
+
+impl Source {
+    /// Read the source from wherever `program` says it comes from.
+    fn read(program: &Program) -> Result<Self, String> {
+        // Commands are ASCII, so the program is read as bytes and needn't be valid UTF-8
+        let mut bytes = vec![];
+        let mut files = vec![];
+        match program {
+            Program::Files(paths) => {
+                for path in paths {
+                    files.push((path.clone(), bytes.len()));
+                    bytes
+                        .extend(std::fs::read(path).map_err(|err| {
+                            format!("cannot read '{}': {}", path, describe(&err))
+                        })?);
                 }
-                Op::Input => {}
-                Op::JumpIfZero(addr) => {}
-                Op::JumpIfNonZero(addr) => {}
             }
+            Program::Eval(source) => bytes = source.clone().into_bytes(),
+            Program::Bytecode(path) => {
+                bytes = std::fs::read(path)
+                    .map_err(|err| format!("cannot read '{}': {}", path, describe(&err)))?;
+                files.push((path.clone(), 0));
+            }
+            Program::Stdin => {
+                io::stdin()
+                    .read_to_end(&mut bytes)
+                    .map_err(|err| format!("cannot read program from stdin: {}", describe(&err)))?;
+            }
+        }
+
+        Ok(Self { bytes, files })
+    }
+
+    /// Locate the byte offset `pos`, counting lines within its own file when the source
+    /// was joined from several.
+    fn locate(&self, pos: usize) -> Location {
+        if self.files.len() < 2 {
+            return Location::new(&self.bytes, pos);
+        }
+
+        let index = self.files.partition_point(|&(_, start)| start <= pos) - 1;
+        let (name, start) = &self.files[index];
+        let end = self
+            .files
+            .get(index + 1)
+            .map_or(self.bytes.len(), |&(_, end)| end);
+        Location::new(&self.bytes[*start..end], pos - start).in_file(name)
+    }
+
+    /// Describe a parse error, pointing into the file it was found in.
+    fn parse_error(&self, mut err: ParseError) -> String {
+        err.set_location(self.locate(err.pos()));
+        err.to_string()
+    }
+}
+
+const USAGE: &str = "usage: brainphoque [OPTIONS] [FILE... | - | -e SOURCE | --run-bytecode FILE]
+Reads the program from the FILEs joined in order, or from stdin when FILE is - or
+missing.";
+
+const HELP: &str = "
+Options:
+  -e, --eval SOURCE      Run SOURCE instead of reading a program
+      --input FILE       Read the program's input from FILE instead of stdin
+      --mode MODE        Run with the JIT (the default where supported) or interpret
+  -c, --cells N          Number of cells on the tape (default 30000)
+  -O0, -O1, -O2          Optimization level (default -O2)
+      --max-depth N      Reject loops nested more than N deep (default 4096)
+      --strict           Reject any byte that is neither a command nor whitespace
+      --emit LANG        Print the program as asm, c, rust or wasm instead of running it
+      --emit obj FILE    Write the program to FILE as a native object file exporting
+                         void bf_main(uint8_t *tape)
+      --emit bytecode FILE
+                         Write the optimized program to FILE as bytecode
+      --run-bytecode FILE
+                         Run bytecode written by --emit bytecode, skipping parsing and
+                         optimization
+      --dump-ops         Print the optimized ops to stderr
+      --no-run           Stop after printing the requested output
+      --count-only       Print counts of the program's ops, loops and commands instead
+                         of running it
+      --exit-with-cell   Exit with the value the first cell ends up with, modulo 256
+  -h, --help             Print this help and exit
+  -V, --version          Print the version and exit
+
+Interpreter options:
+      --input-string TEXT
+                         Use TEXT as the program's input instead of stdin
+      --pointer MODE     Past either end of the tape: strict errors (the default), grow
+                         extends the tape and wrap goes round to the other end
+      --bidirectional    Same as --pointer grow
+      --start-offset N   Start the data pointer on cell N, leaving N cells to its left
+      --cell-bits BITS   Cell width: 8, 16 or 32 (default 8)
+      --output-mode MODE Write each cell . prints as a raw byte (the default) or as
+                         a decimal number followed by a space
+      --input-filter F   Translate the program's input with filter F: crlf-to-lf,
+                         lf-to-crlf, uppercase or lowercase. Repeat to apply more
+                         than one, in order
+      --output-filter F  Translate the program's output the same way
+      --max-steps N      Stop after executing N ops
+      --timeout MS       Stop after MS milliseconds
+      --stats            Print execution statistics to stderr
+      --profile          Print the loops that executed the most ops to stderr
+      --hot-loops        Print the loops that iterated the most to stderr
+      --dump-tape        Print every cell the program reached to stderr
+      --trace            Log each executed op to stderr
+      --trace-limit N    Log at most N ops, implying --trace
+      --debug            Step through the program interactively
+      --repl             Run lines typed on stdin one at a time on the same tape
+      --extensions       Treat # as a command that dumps the tape
+
+JIT options:
+      --dump-asm         Print the machine code for each op to stderr
+      --dump-machine-code FILE
+                         Write the raw machine code to FILE; with --no-run the code
+                         is only generated, never mapped
+      --safe-jit         Trap when the data pointer leaves the tape
+      --guarded-tape     Surround the tape with guard pages so overruns fault";
+
+fn main() -> ExitCode {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if options.help {
+        println!("{}\n{}", USAGE, HELP);
+        return ExitCode::SUCCESS;
+    }
+    if options.version {
+        println!("brainphoque {}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::SUCCESS;
+    }
+
+    if matches!(options.program, Program::Stdin) && io::stdin().is_terminal() && !options.repl {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    match run(&options) {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Load, compile and execute the program, returning the exit status or a message
+/// describing any failure.
+fn run(options: &Options) -> Result<ExitCode, String> {
+    if options.repl {
+        if !matches!(options.program, Program::Stdin) {
+            return Err("--repl reads the program from stdin".to_string());
+        }
+        options.mode()?;
+
+        let reader = input_reader(options, open_input(options)?);
+        match options.cell_bits {
+            CellBits::U8 => repl::<u8>(options, reader)?,
+            CellBits::U16 => repl::<u16>(options, reader)?,
+            CellBits::U32 => repl::<u32>(options, reader)?,
+        }
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let source = Source::read(&options.program)?;
+    let program = &source.bytes;
+
+    let input = open_input(options)?;
+
+    if let Program::Bytecode(path) = &options.program {
+        // Bytecode keeps no source to step through or count commands in
+        if options.debug {
+            return Err("--debug needs the program's source, not bytecode".to_string());
+        }
+        if options.count_only {
+            return Err("--count-only needs the program's source, not bytecode".to_string());
+        }
+
+        let operations =
+            load_bytecode(program).map_err(|err| format!("cannot load '{}': {}", path, err))?;
+        return execute(operations, &Origin::Bytecode, options, input);
+    }
+
+    if options.debug {
+        // Unoptimized ops map one to one onto the commands in the source
+        let (operations, positions) = parse_with_positions(
+            program,
+            options.extensions,
+            options.strict,
+            options.max_depth,
+        )
+        .map_err(|err| source.parse_error(err))?;
+        let reader = input_reader(options, input);
+
+        match options.cell_bits {
+            CellBits::U8 => debug::<u8>(operations, &positions, options, reader)?,
+            CellBits::U16 => debug::<u16>(operations, &positions, options, reader)?,
+            CellBits::U32 => debug::<u32>(operations, &positions, options, reader)?,
+        }
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let (operations, positions) = parse_with_positions(
+        program,
+        options.extensions,
+        options.strict,
+        options.max_depth,
+    )
+    .map_err(|err| source.parse_error(err))?;
+    if options.count_only {
+        write_stdout(&format!("{}\n", ProgramStats::new(&operations)))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let (operations, positions) = optimize_with_positions(operations, positions, options.opt_level);
+
+    execute(
+        operations,
+        &Origin::Source(&source, &positions),
+        options,
+        input,
+    )
+}
+
+/// Where the ops being run came from, to point diagnostics at.
+enum Origin<'a> {
+    /// Parsed from a source, with the source byte offset of each op
+    Source(&'a Source, &'a [usize]),
+    /// Loaded from bytecode, which keeps no positions
+    Bytecode,
+}
+
+impl Origin<'_> {
+    /// Where the op at `ip` is, with the source line it's on when there is one.
+    fn locate(&self, ip: usize) -> String {
+        match self {
+            Origin::Source(source, positions) => source.locate(positions[ip]).to_string(),
+            Origin::Bytecode => format!("op {}", ip),
+        }
+    }
+
+    /// Where the op at `ip` is, in a few characters: `file:line:column`, `line:column`
+    /// or the op index.
+    fn short_location(&self, ip: usize) -> String {
+        let Origin::Source(source, positions) = self else {
+            return format!("op {}", ip);
+        };
+
+        let location = source.locate(positions[ip]);
+        match &location.file {
+            Some(file) => format!("{}:{}:{}", file, location.line, location.column),
+            None => format!("{}:{}", location.line, location.column),
+        }
+    }
+}
+
+/// Emit or run the optimized program, returning the exit status or a message describing
+/// any failure.
+fn execute(
+    operations: Vec<Op>,
+    origin: &Origin,
+    options: &Options,
+    input: Option<File>,
+) -> Result<ExitCode, String> {
+    if let Some(emit) = &options.emit {
+        match emit {
+            Emit::Asm => write_stdout(&emit_asm(&operations, options.cells))?,
+            Emit::C => write_stdout(&emit_c(&operations, options.cells))?,
+            Emit::Rust => write_stdout(&emit_rust(&operations, options.cells))?,
+            Emit::Wasm => write_stdout(&emit_wasm(&operations, options.cells))?,
+            Emit::Object(path) => write_object(operations, path)?,
+            Emit::Bytecode(path) => std::fs::write(path, emit_bytecode(&operations))
+                .map_err(|err| format!("cannot write '{}': {}", path, describe(&err)))?,
+        }
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if options.dump_ops {
+        eprint!("{}", dump_ops(&operations));
+    }
+
+    let mode = options.mode()?;
+    // The JIT only has its listing once the program is compiled
+    if options.no_run && mode == Mode::Interpret {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if mode == Mode::Interpret {
+        let reader = input_reader(options, input);
+
+        let result = match options.cell_bits {
+            CellBits::U8 => interpret::<u8>(operations, options, reader),
+            CellBits::U16 => interpret::<u16>(operations, options, reader),
+            CellBits::U32 => interpret::<u32>(operations, options, reader),
+        };
+        let (stats, first_cell) = match result {
+            Ok(result) => result,
+            // Whoever was reading the output is gone, e.g. when piping into `head`
+            Err((ExecutionError::Io(err), _)) if err.kind() == io::ErrorKind::BrokenPipe => {
+                return Ok(ExitCode::SUCCESS)
+            }
+            Err((err, ip)) => return Err(format!("{} at {}", err, origin.locate(ip))),
+        };
+
+        if options.stats {
+            eprintln!("{}", stats);
+        }
+        if options.profile {
+            eprint!("{}", profile_report(&stats, origin));
         }
+        if options.hot_loops {
+            eprint!("{}", hot_loops_report(&stats, origin));
+        }
+
+        return Ok(exit_code(options, first_cell));
+    }
+
+    run_jit(operations, options, input)
+}
 
-        code.extend_from_slice(&[0xC0, 0x03, 0x5F, 0xD6]);
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+/// Compile and run the program, exiting successfully unless reading its input or writing
+/// its output failed, or with the first cell for `--exit-with-cell`.
+fn run_jit(
+    operations: Vec<Op>,
+    options: &Options,
+    input: Option<File>,
+) -> Result<ExitCode, String> {
+    use std::os::unix::io::AsRawFd;
 
-        unsafe {
-            pthread_jit_write_protect_np(0);
+    // Compiled code reads `,` straight from the stdin file descriptor
+    if let Some(file) = input {
+        if unsafe { libc::dup2(file.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+            return Err(format!(
+                "cannot redirect input: {}",
+                describe(&io::Error::last_os_error())
+            ));
         }
+    }
+
+    let jit_compiler = JitCompiler::new(operations).bounds_checks(options.safe_jit);
+    if options.dump_asm {
+        eprint!("{}", jit_compiler.listing().map_err(|e| e.to_string())?);
+    }
+    if let Some(path) = &options.dump_machine_code {
+        let code = jit_compiler.codegen().map_err(|e| e.to_string())?;
+        std::fs::write(path, code)
+            .map_err(|err| format!("cannot write '{}': {}", path, describe(&err)))?;
+    }
+    if options.no_run {
+        return Ok(ExitCode::SUCCESS);
+    }
+    let compiled = jit_compiler.compile().map_err(|err| match err {
+        CompileError::Map(err) => format!("cannot map the compiled program: {}", describe(&err)),
+        err => err.to_string(),
+    })?;
+    let status = if options.guarded_tape {
+        // Running off the tape faults on a guard page; that crash is the intended outcome
+        let tape = GuardedTape::new(options.cells).map_err(|e| e.to_string())?;
+        unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) }
+    } else {
+        let mut tape = Tape::new(options.cells);
+        unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) }
+    };
+
+    match status {
+        // Whoever was reading the output is gone, e.g. when piping into `head`
+        status if status == -libc::EPIPE => Ok(ExitCode::SUCCESS),
+        status if status < 0 => Err(format!(
+            "I/O error: {}",
+            describe(&io::Error::from_raw_os_error(-status))
+        )),
+        // Otherwise it's the first cell
+        first_cell => Ok(exit_code(options, first_cell as u8)),
+    }
+}
+
+#[cfg(not(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+)))]
+fn run_jit(
+    _operations: Vec<Op>,
+    _options: &Options,
+    _input: Option<File>,
+) -> Result<ExitCode, String> {
+    Err("this build has no JIT: it needs the jit feature and a supported platform".to_string())
+}
+
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+/// Write the program's machine code to `path` as an object file.
+fn write_object(operations: Vec<Op>, path: &str) -> Result<(), String> {
+    let object = JitCompiler::new(operations)
+        .object()
+        .map_err(|e| e.to_string())?;
+    std::fs::write(path, object)
+        .map_err(|err| format!("cannot write '{}': {}", path, describe(&err)))
+}
+
+#[cfg(not(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+)))]
+fn write_object(_operations: Vec<Op>, _path: &str) -> Result<(), String> {
+    Err("this build has no JIT to generate object files: it needs the jit feature and a supported platform".to_string())
+}
+
+/// Open the `--input` file, if there is one.
+fn open_input(options: &Options) -> Result<Option<File>, String> {
+    match &options.input {
+        // A dry run never reads input, and opening a named pipe would block until it had
+        // a writer
+        Input::File(path) if !options.no_run && !options.count_only => File::open(path)
+            .map(Some)
+            .map_err(|err| format!("cannot read '{}': {}", path, describe(&err))),
+        _ => Ok(None),
+    }
+}
+
+/// The reader `,` takes the program's input from, given the opened `--input` file.
+fn input_reader(options: &Options, file: Option<File>) -> Box<dyn Read> {
+    let reader: Box<dyn Read> = match (&options.input, file) {
+        (_, Some(file)) => Box::new(BufReader::new(file)),
+        (Input::String(text), None) => Box::new(io::Cursor::new(text.clone().into_bytes())),
+        _ => Box::new(io::stdin()),
+    };
+
+    if options.input_filters.is_empty() {
+        reader
+    } else {
+        Box::new(FilterReader::new(reader, &options.input_filters))
+    }
+}
+
+/// Number of loops listed by `--profile` and `--hot-loops`
+const PROFILE_LOOPS: usize = 10;
+
+/// List the hottest loops with where they start, the number of iterations and the share of
+/// all executed ops spent inside them.
+fn profile_report(stats: &RunStats, origin: &Origin) -> String {
+    if stats.loops.is_empty() {
+        return "no loops ran\n".to_string();
+    }
+
+    let mut report = String::from("hottest loops:\n");
+    for profile in stats.loops.iter().take(PROFILE_LOOPS) {
+        let share = 100.0 * profile.ops_executed as f64 / stats.ops_executed as f64;
+        report.push_str(&format!(
+            "  loop at {:<9} {:>12} iterations {:>14} ops ({:.1}%)\n",
+            origin.short_location(profile.start),
+            profile.iterations,
+            profile.ops_executed,
+            share
+        ));
+    }
+
+    report
+}
 
-        let mem = unsafe {
-            libc::mmap(
-                ptr::null_mut(),
-                code.len(),
-                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
-                libc::MAP_ANON | libc::MAP_PRIVATE | libc::MAP_JIT,
-                -1,
-                0,
-            )
+/// List the loops with the most iterations with where they start and end.
+fn hot_loops_report(stats: &RunStats, origin: &Origin) -> String {
+    if stats.loops.is_empty() {
+        return "no loops ran\n".to_string();
+    }
+
+    let mut report = String::from("hot loops:\n");
+    for profile in stats.hot_loops().into_iter().take(PROFILE_LOOPS) {
+        report.push_str(&format!(
+            "  loop at {:<9} to {:<9} {:>12} iterations\n",
+            origin.short_location(profile.start),
+            origin.short_location(profile.end),
+            profile.iterations
+        ));
+    }
+
+    report
+}
+
+/// Write generated code to stdout, stopping quietly if the reader has gone away.
+fn write_stdout(text: &str) -> Result<(), String> {
+    match io::stdout().write_all(text.as_bytes()) {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+            Err(format!("cannot write output: {}", describe(&err)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Describe an I/O error without the `(os error N)` suffix.
+fn describe(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+/// The status a program that ran to the end exits with: success, unless `--exit-with-cell`
+/// asks for `first_cell`.
+fn exit_code(options: &Options, first_cell: u8) -> ExitCode {
+    if options.exit_with_cell {
+        ExitCode::from(first_cell)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Run the program, returning its statistics and the low byte of its first cell, or the
+/// index of the op that failed along with the error.
+fn interpret<C: Cell>(
+    operations: Vec<Op>,
+    options: &Options,
+    reader: Box<dyn Read>,
+) -> Result<(RunStats, u8), (ExecutionError, usize)> {
+    let stdout = FilterWriter::new(io::stdout(), &options.output_filters);
+    let mut interpreter =
+        Interpreter::<_, _, C>::with_cell_type(operations, options.cells, reader, stdout)
+            .pointer_mode(options.pointer)
+            .start_offset(options.start_offset)
+            .output_mode(options.output_mode)
+            .max_steps(options.max_steps)
+            .timeout(options.timeout)
+            .trace(options.trace)
+            .trace_limit(options.trace_limit);
+
+    let stats = interpreter.run().map_err(|err| (err, interpreter.ip()))?;
+    if options.dump_tape {
+        let dp = interpreter.dp();
+        eprint!("{}", tape_dump(interpreter.tape(), dp));
+    }
+
+    Ok((stats, interpreter.cell(0).to_byte()))
+}
+
+/// Number of cells on each line of `--dump-tape`
+const TAPE_DUMP_COLUMNS: usize = 16;
+
+/// Lay `cells` out in rows, each starting with the index of its first cell, with the
+/// cell at `dp` bracketed.
+fn tape_dump<C: Cell>(cells: &[C], dp: usize) -> String {
+    let values: Vec<String> = cells.iter().map(|cell| cell.to_string()).collect();
+    let width = values.iter().map(String::len).max().unwrap_or(1);
+    let index_width = (cells.len() - 1).to_string().len();
+
+    let mut dump = String::new();
+    for (row, chunk) in values.chunks(TAPE_DUMP_COLUMNS).enumerate() {
+        let start = row * TAPE_DUMP_COLUMNS;
+        dump.push_str(&format!("{:>1$}:", start, index_width));
+        for (index, value) in (start..).zip(chunk) {
+            if index == dp {
+                dump.push_str(&format!(" [{:>1$}]", value, width));
+            } else {
+                dump.push_str(&format!("  {:>1$} ", value, width));
+            }
+        }
+        dump.truncate(dump.trim_end().len());
+        dump.push('\n');
+    }
+
+    dump
+}
+
+/// Read lines of source from stdin and run each as soon as its loops are closed, on a
+/// tape that carries over from one line to the next.
+fn repl<C: Cell>(options: &Options, reader: Box<dyn Read>) -> Result<(), String> {
+    let stdout = FilterWriter::new(io::stdout(), &options.output_filters);
+    let mut interpreter =
+        Interpreter::<_, _, C>::with_cell_type(vec![], options.cells, reader, stdout)
+            .pointer_mode(options.pointer)
+            .start_offset(options.start_offset)
+            .output_mode(options.output_mode)
+            .max_steps(options.max_steps)
+            .timeout(options.timeout)
+            .trace(options.trace)
+            .trace_limit(options.trace_limit);
+    // Source of a loop still waiting for its `]`
+    let mut source = String::new();
+
+    loop {
+        eprint!("{}", if source.is_empty() { "bf> " } else { "... " });
+        let read = io::stdin()
+            .read_line(&mut source)
+            .map_err(|err| format!("cannot read from stdin: {}", describe(&err)))?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        // Lines start on whatever the ones before left on the tape, so they aren't
+        // optimized: the optimizer assumes a program starts on a zeroed tape
+        let ops = match parse_with_positions(
+            &source,
+            options.extensions,
+            options.strict,
+            options.max_depth,
+        ) {
+            Ok((ops, _)) => ops,
+            Err(ParseError::UnmatchedOpen { .. }) => continue,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                source.clear();
+                continue;
+            }
         };
+        source.clear();
+
+        // Running flushes whatever the line printed
+        interpreter.extend(ops);
+        match interpreter.run() {
+            Err(ExecutionError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {
+                return Ok(())
+            }
+            Err(err) => eprintln!("error: {}", err),
+            Ok(_) => {}
+        }
+    }
+}
+
+const DEBUG_HELP: &str = "commands:
+  s      execute the next op
+  c      continue until a breakpoint or the end of the program
+  p      print the cells around the data pointer
+  b N    break before the op at source position N
+  k      save a checkpoint of the pointers and tape
+  r      rewind to the checkpoint (output and input are not undone)
+  q      quit";
 
-        if mem == libc::MAP_FAILED {
-            let err = Error::last_os_error();
-            println!("Error code: {:?}", err.raw_os_error());
-            panic!("Failed to allocate executable memory");
+/// Run the interpreter under an interactive debugger that reads commands from stdin.
+///
+/// `positions` holds the source byte offset of each op, for breakpoints. The debugger
+/// talks on stderr so the program's own output stays on stdout.
+fn debug<C: Cell>(
+    operations: Vec<Op>,
+    positions: &[usize],
+    options: &Options,
+    reader: Box<dyn Read>,
+) -> Result<(), String> {
+    let stdout = FilterWriter::new(io::stdout(), &options.output_filters);
+    let mut interpreter =
+        Interpreter::<_, _, C>::with_cell_type(operations, options.cells, reader, stdout)
+            .pointer_mode(options.pointer)
+            .start_offset(options.start_offset)
+            .output_mode(options.output_mode)
+            .max_steps(options.max_steps);
+    // Op indices to stop before
+    let mut breakpoints = BTreeSet::new();
+    let mut checkpoint = None;
+
+    eprintln!("{}", DEBUG_HELP);
+
+    loop {
+        let ip = interpreter.ip();
+        let dp = interpreter.dp();
+        match interpreter.ops().get(ip) {
+            Some(op) => eprintln!(
+                "ip={} dp={} cell={} next=Op::{} at position {}",
+                ip,
+                dp,
+                interpreter.cell(dp),
+                op.name(),
+                positions[ip]
+            ),
+            None => {
+                eprintln!("program finished");
+                return Ok(());
+            }
         }
 
-        unsafe {
-            ptr::copy_nonoverlapping(code.as_ptr(), mem as *mut u8, code.len());
-            pthread_jit_write_protect_np(1);
+        eprint!("(debug) ");
+        let mut line = String::new();
+        let read = io::stdin()
+            .read_line(&mut line)
+            .map_err(|err| format!("cannot read debugger command: {}", describe(&err)))?;
+        if read == 0 {
+            return Ok(());
         }
 
-        let func: extern "C" fn(memory: *const u8) = unsafe { std::mem::transmute(mem) };
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("s"), None) => {
+                interpreter.step().map_err(|err| err.to_string())?;
+            }
+            (Some("c"), None) => {
+                while interpreter.step().map_err(|err| err.to_string())? {
+                    if breakpoints.contains(&interpreter.ip()) {
+                        break;
+                    }
+                }
+            }
+            (Some("p"), None) => {
+                // 10 cells centered on the data pointer
+                for index in dp.saturating_sub(5)..dp.saturating_sub(5) + 10 {
+                    let marker = if index == dp { ">" } else { " " };
+                    eprintln!("{} {:>6}: {}", marker, index, interpreter.cell(index));
+                }
+            }
+            (Some("b"), Some(position)) => {
+                let position: usize = match position.parse() {
+                    Ok(position) => position,
+                    Err(_) => {
+                        eprintln!("b expects a source position, got '{}'", position);
+                        continue;
+                    }
+                };
+
+                // Stop at the first command at or after the position
+                match positions.iter().position(|&pos| pos >= position) {
+                    Some(op) => {
+                        breakpoints.insert(op);
+                        eprintln!("breakpoint set at position {}", positions[op]);
+                    }
+                    None => eprintln!("no command at or after position {}", position),
+                }
+            }
+            (Some("k"), None) => {
+                checkpoint = Some(interpreter.snapshot());
+                eprintln!("checkpoint saved");
+            }
+            (Some("r"), None) => match &checkpoint {
+                Some(state) => interpreter.restore(state),
+                None => eprintln!("no checkpoint saved"),
+            },
+            (Some("q"), None) => return Ok(()),
+            _ => eprintln!("{}", DEBUG_HELP),
+        }
 
-        func
+        interpreter
+            .flush()
+            .map_err(|err| format!("cannot write output: {}", describe(&err)))?;
     }
 }
@@ -0,0 +1,214 @@
+use std::{fmt::Write, mem};
+
+#[derive(Debug)]
+pub enum Op {
+    /// +
+    /// Increment the byte at the data pointer by one.
+    Inc,
+    /// -
+    /// Decrement the byte at the data pointer by one.
+    Dec,
+    /// <
+    /// Decrement the data pointer by one (to point to the next cell to the left).
+    MoveLeft,
+    /// >
+    /// Increment the data pointer by one (to point to the next cell to the right).
+    MoveRight,
+    /// .
+    /// Output the byte at the data pointer.
+    Output,
+    /// ,
+    /// Accept one byte of input, storing its value in the byte at the data pointer.
+    Input,
+    /// [
+    /// If the byte at the data pointer is zero, then instead of moving the instruction pointer forward to the next command,
+    /// jump it forward to the command after the matching ] command.
+    JumpIfZero,
+    /// ]
+    /// If the byte at the data pointer is nonzero, then instead of moving the instruction pointer forward to the next command,
+    /// jump it back to the command after the matching [ command.
+    JumpIfNonZero,
+    /// A run of + and -
+    /// Add the net delta to the byte at the data pointer.
+    Add(i8),
+    /// A run of < and >
+    /// Move the data pointer by the net delta.
+    Move(isize),
+    /// [-] or [+]
+    /// Set the byte at the data pointer to zero.
+    SetZero,
+    /// Part of a multiply loop such as [->++<]
+    /// Add the byte at the data pointer times `factor` to the cell `offset` cells away.
+    AddMul { offset: isize, factor: i8 },
+    /// A run of .
+    /// Output the byte at the data pointer `count` times.
+    OutputRepeat(usize),
+    /// Part of a run of + - < and > such as >>+<<
+    /// Add `delta` to the cell `offset` cells away, without moving the data pointer.
+    AddAtOffset { offset: isize, delta: i8 },
+    /// [>]
+    /// Move the data pointer right to the nearest zero cell, staying put if the current
+    /// cell is zero.
+    ScanRight,
+    /// [<]
+    /// Move the data pointer left to the nearest zero cell, staying put if the current
+    /// cell is zero.
+    ScanLeft,
+    /// [-] followed by a run of + and -, such as [-]+++
+    /// Set the byte at the data pointer to the net delta, wrapping negative deltas.
+    SetConst(i8),
+    /// # (extension)
+    /// Print the data pointer and the cells around it to stderr.
+    DebugDump,
+    /// [...] in the tree form returned by [`parse_tree`](crate::parse_tree)
+    /// Run the body for as long as the byte at the data pointer is nonzero.
+    ///
+    /// Nothing runs this directly: [`lower`] turns it back into [`Op::JumpIfZero`] and
+    /// [`Op::JumpIfNonZero`] around its body.
+    Loop(Vec<Op>),
+}
+
+impl Op {
+    /// Name of the op's variant, without its operands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Inc => "Inc",
+            Op::Dec => "Dec",
+            Op::MoveLeft => "MoveLeft",
+            Op::MoveRight => "MoveRight",
+            Op::Output => "Output",
+            Op::Input => "Input",
+            Op::JumpIfZero => "JumpIfZero",
+            Op::JumpIfNonZero => "JumpIfNonZero",
+            Op::Add(_) => "Add",
+            Op::Move(_) => "Move",
+            Op::SetZero => "SetZero",
+            Op::AddMul { .. } => "AddMul",
+            Op::OutputRepeat(_) => "OutputRepeat",
+            Op::AddAtOffset { .. } => "AddAtOffset",
+            Op::ScanRight => "ScanRight",
+            Op::ScanLeft => "ScanLeft",
+            Op::SetConst(_) => "SetConst",
+            Op::DebugDump => "DebugDump",
+            Op::Loop(_) => "Loop",
+        }
+    }
+}
+
+impl Drop for Op {
+    /// Free the ops of nested loops one level at a time, since the recursive drop would
+    /// overflow the call stack on loops nested as deep as the parser allows.
+    fn drop(&mut self) {
+        let Op::Loop(body) = self else {
+            return;
+        };
+
+        let mut pending = mem::take(body);
+        while let Some(mut op) = pending.pop() {
+            if let Op::Loop(body) = &mut op {
+                pending.append(body);
+            }
+        }
+    }
+}
+
+/// List `ops` one per line with their index, e.g. `3: JumpIfZero -> 9`. Jumps show the
+/// index of the op they continue at.
+pub fn dump_ops(ops: &[Op]) -> String {
+    let jump_table = jump_table(ops);
+    let mut listing = String::new();
+
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::JumpIfZero | Op::JumpIfNonZero => {
+                writeln!(listing, "{}: {:?} -> {}", i, op, jump_table[i]).unwrap()
+            }
+            _ => writeln!(listing, "{}: {:?}", i, op).unwrap(),
+        }
+    }
+
+    listing
+}
+
+/// Build the jump table for `ops`: each `[` maps to the op after its matching `]`, and
+/// each `]` to the op after its matching `[`. Other entries are unused.
+///
+/// Built once the op layout is final, so optimizer passes are free to add and remove ops.
+pub(crate) fn jump_table(ops: &[Op]) -> Vec<usize> {
+    let mut table = vec![0; ops.len()];
+    let mut jump_op_stack = vec![];
+
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::JumpIfZero => jump_op_stack.push(i),
+            Op::JumpIfNonZero => {
+                let start = jump_op_stack.pop().expect("unbalanced jumps");
+                table[start] = i + 1;
+                table[i] = start + 1;
+            }
+            _ => {}
+        }
+    }
+
+    table
+}
+
+/// Flatten the tree form of a program, as returned by [`parse_tree`](crate::parse_tree),
+/// into the ops the backends run: each [`Op::Loop`] becomes its body between
+/// [`Op::JumpIfZero`] and [`Op::JumpIfNonZero`].
+///
+/// Loops are walked with a stack rather than by recursion, so nesting as deep as the
+/// parser allows can't overflow the call stack.
+pub fn lower(ops: Vec<Op>) -> Vec<Op> {
+    let mut lowered = Vec::with_capacity(ops.len());
+    // Ops left to lower in each loop being lowered, the program first
+    let mut blocks = vec![ops.into_iter()];
+
+    while let Some(rest) = blocks.last_mut() {
+        match rest.next() {
+            Some(mut op) => match &mut op {
+                Op::Loop(body) => {
+                    lowered.push(Op::JumpIfZero);
+                    blocks.push(mem::take(body).into_iter());
+                }
+                _ => lowered.push(op),
+            },
+            None => {
+                blocks.pop();
+                if !blocks.is_empty() {
+                    lowered.push(Op::JumpIfNonZero);
+                }
+            }
+        }
+    }
+
+    lowered
+}
+
+/// Nest flat ops into the tree form, the reverse of [`lower`]: the ops between each
+/// [`Op::JumpIfZero`] and its [`Op::JumpIfNonZero`] become an [`Op::Loop`].
+///
+/// # Panics
+///
+/// If the jumps in `ops` are unbalanced, which they never are in parsed programs.
+pub fn nest(ops: Vec<Op>) -> Vec<Op> {
+    // Ops of each loop still waiting for its `]`, outermost first
+    let mut blocks = vec![vec![]];
+
+    for op in ops {
+        match op {
+            Op::JumpIfZero => blocks.push(vec![]),
+            Op::JumpIfNonZero => {
+                let body = blocks.pop().expect("unbalanced jumps");
+                blocks
+                    .last_mut()
+                    .expect("unbalanced jumps")
+                    .push(Op::Loop(body));
+            }
+            op => blocks.last_mut().unwrap().push(op),
+        }
+    }
+
+    assert_eq!(blocks.len(), 1, "unbalanced jumps");
+    blocks.pop().unwrap()
+}
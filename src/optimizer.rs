@@ -0,0 +1,472 @@
+use std::{collections::BTreeMap, mem};
+
+use crate::{lower, nest, Op};
+
+/// Which optimization passes to run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// No passes: ops map one to one onto the commands in the source.
+    O0,
+    /// Drop loops that can never run, fold runs of commands and replace clear loops.
+    O1,
+    /// Every pass, adding scan loops, multiply loops, constant stores, folding of the
+    /// program's start on the zeroed tape and offset blocks on top of `O1`.
+    /// This is the default.
+    #[default]
+    O2,
+}
+
+/// Run every optimization pass over `ops`, returning an equivalent program.
+///
+/// The result is only equivalent when run on a zeroed tape: from `O1` on, loops at the
+/// very start of the program are dropped because they can't run, and at `O2` the cells
+/// the start of the program computes are written out as constants.
+pub fn optimize(ops: Vec<Op>) -> Vec<Op> {
+    optimize_with_level(ops, OptLevel::O2)
+}
+
+/// Run the optimization passes enabled at `level` over `ops`, returning an equivalent
+/// program.
+pub fn optimize_with_level(ops: Vec<Op>, level: OptLevel) -> Vec<Op> {
+    let positions = vec![0; ops.len()];
+    optimize_with_positions(ops, positions, level).0
+}
+
+/// Like [`optimize_with_level`], but carry the source position of each op through the
+/// passes, as returned by [`parse_with_positions`](crate::parse_with_positions).
+///
+/// An op that replaces several others takes the position of the first one, so a folded
+/// run points at its first command and an optimized loop at its `[`.
+pub fn optimize_with_positions(
+    ops: Vec<Op>,
+    positions: Vec<usize>,
+    level: OptLevel,
+) -> (Vec<Op>, Vec<usize>) {
+    if level == OptLevel::O0 {
+        return (ops, positions);
+    }
+
+    let (ops, positions) = fold_runs(dead_loops(ops.into_iter().zip(positions).collect()))
+        .into_iter()
+        .unzip();
+    let mut tree = clear_loops((nest(ops), positions));
+    if level >= OptLevel::O2 {
+        tree = multiply_loops(scan_loops(tree));
+    }
+
+    let (ops, positions) = tree;
+    let mut ops = lower(ops).into_iter().zip(positions).collect();
+    if level >= OptLevel::O2 {
+        ops = offset_blocks(known_start(set_consts(ops)));
+    }
+
+    ops.into_iter().unzip()
+}
+
+/// A program nested into loops by [`nest`], with the source position of each op in the
+/// order [`lower`] lays them out, `[` and `]` included.
+type Tree = (Vec<Op>, Vec<usize>);
+
+/// Replace every loop whose body `replace` returns ops for with those ops, innermost
+/// loops first, each at the position of the loop's `[`.
+///
+/// The tree is walked with a stack rather than by recursion, so loops nested as deep as
+/// the parser allows can't overflow the call stack.
+fn replace_loops((ops, positions): Tree, replace: impl Fn(&[Op]) -> Option<Vec<Op>>) -> Tree {
+    let mut positions = positions.into_iter();
+    let mut replaced = Vec::with_capacity(positions.len());
+    // Each block being rebuilt, the program first: the ops left to visit, the ops rebuilt
+    // so far and the index of the block's `[` in `replaced`
+    let mut blocks = vec![(ops.into_iter(), vec![], 0)];
+
+    loop {
+        let (rest, rebuilt, _) = blocks.last_mut().unwrap();
+        let pos = positions.next();
+
+        match rest.next() {
+            Some(mut op) => match &mut op {
+                Op::Loop(body) => {
+                    let start = replaced.len();
+                    replaced.extend(pos);
+                    blocks.push((mem::take(body).into_iter(), vec![], start));
+                }
+                _ => {
+                    replaced.extend(pos);
+                    rebuilt.push(op);
+                }
+            },
+            None => {
+                let (_, body, start) = blocks.pop().unwrap();
+                let Some((_, rebuilt, _)) = blocks.last_mut() else {
+                    return (body, replaced);
+                };
+
+                match replace(&body) {
+                    Some(replacement) => {
+                        let pos = replaced[start];
+                        replaced.truncate(start);
+                        replaced.extend(replacement.iter().map(|_| pos));
+                        rebuilt.extend(replacement);
+                    }
+                    None => {
+                        // The loop's `]`
+                        replaced.extend(pos);
+                        rebuilt.push(Op::Loop(body));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Remove loops that start where the current cell is known to be zero, so their body
+/// never runs: at the very start of the program, where every cell is zero, and straight
+/// after another loop, which only exits on a zero cell. `[-][>+<]` keeps only `[-]`.
+fn dead_loops(ops: Vec<(Op, usize)>) -> Vec<(Op, usize)> {
+    let mut live: Vec<(Op, usize)> = Vec::with_capacity(ops.len());
+    // Nesting depth inside the dead loop being skipped, if any
+    let mut skipping = 0;
+
+    for (op, pos) in ops {
+        if skipping > 0 {
+            match op {
+                Op::JumpIfZero => skipping += 1,
+                Op::JumpIfNonZero => skipping -= 1,
+                _ => {}
+            }
+            continue;
+        }
+
+        match (live.last(), &op) {
+            (None | Some((Op::JumpIfNonZero, _)), Op::JumpIfZero) => skipping = 1,
+            _ => live.push((op, pos)),
+        }
+    }
+
+    live
+}
+
+/// Fold runs of `+`/`-` into [`Op::Add`], runs of `<`/`>` into [`Op::Move`] and runs of
+/// `.` into [`Op::OutputRepeat`].
+///
+/// Cell deltas are kept within `i8` so folding never depends on the cell width: a run
+/// longer than 127 is split across several `Add`s. Runs that cancel out are dropped.
+fn fold_runs(ops: Vec<(Op, usize)>) -> Vec<(Op, usize)> {
+    let mut folded: Vec<(Op, usize)> = Vec::with_capacity(ops.len());
+
+    for (op, pos) in ops {
+        let op = match op {
+            Op::Inc => Op::Add(1),
+            Op::Dec => Op::Add(-1),
+            Op::MoveLeft => Op::Move(-1),
+            Op::MoveRight => Op::Move(1),
+            op => op,
+        };
+
+        match (folded.last_mut().map(|(last, _)| last), &op) {
+            (Some(Op::Add(delta)), Op::Add(more)) => match delta.checked_add(*more) {
+                Some(0) => {
+                    folded.pop();
+                }
+                Some(sum) => *delta = sum,
+                None => folded.push((op, pos)),
+            },
+            (Some(Op::Move(delta)), Op::Move(more)) => match *delta + *more {
+                0 => {
+                    folded.pop();
+                }
+                sum => *delta = sum,
+            },
+            (Some(last @ Op::Output), Op::Output) => *last = Op::OutputRepeat(2),
+            (Some(Op::OutputRepeat(count)), Op::Output) => *count += 1,
+            _ => folded.push((op, pos)),
+        }
+    }
+
+    folded
+}
+
+/// Replace loops that only step the cell by an odd amount, such as `[-]`, `[+]` or
+/// `[---]`, with [`Op::SetZero`].
+///
+/// An odd step shares no factor with the number of values a cell holds, a power of two
+/// whatever the cell width, so stepping from any value reaches zero. An even step like
+/// `[--]` never reaches zero from an odd value, so it stays a loop that can run forever.
+fn clear_loops(tree: Tree) -> Tree {
+    replace_loops(tree, |body| match body {
+        [Op::Add(step)] if step % 2 != 0 => Some(vec![Op::SetZero]),
+        _ => None,
+    })
+}
+
+/// Replace `[>]` and `[<]` loops with [`Op::ScanRight`] and [`Op::ScanLeft`].
+fn scan_loops(tree: Tree) -> Tree {
+    replace_loops(tree, |body| match body {
+        [Op::Move(1)] => Some(vec![Op::ScanRight]),
+        [Op::Move(-1)] => Some(vec![Op::ScanLeft]),
+        _ => None,
+    })
+}
+
+/// Replace loops that only add multiples of the current cell to its neighbours, such as
+/// `[->+>+++<<]`, with one [`Op::AddMul`] per neighbour followed by [`Op::SetZero`].
+///
+/// Only loops made of [`Op::Add`] and [`Op::Move`] qualify, and only when they return to
+/// the starting cell and decrement it by exactly one per iteration. Anything else (I/O,
+/// nested loops, net pointer movement) is left alone.
+fn multiply_loops(tree: Tree) -> Tree {
+    replace_loops(tree, |body| {
+        let factors = multiply_factors(body)?;
+        let adds = factors
+            .into_iter()
+            .map(|(offset, factor)| Op::AddMul { offset, factor });
+        Some(adds.chain([Op::SetZero]).collect())
+    })
+}
+
+/// Replace [`Op::SetZero`] followed by [`Op::Add`], as in `[-]+++`, with [`Op::SetConst`].
+fn set_consts(ops: Vec<(Op, usize)>) -> Vec<(Op, usize)> {
+    let mut fused: Vec<(Op, usize)> = Vec::with_capacity(ops.len());
+
+    for (op, pos) in ops {
+        match (fused.last_mut(), op) {
+            (Some((last @ Op::SetZero, _)), Op::Add(value)) => *last = Op::SetConst(value),
+            (_, op) => fused.push((op, pos)),
+        }
+    }
+
+    fused
+}
+
+/// Largest value [`known_start`] tracks a cell up to, so writing a cell out never takes
+/// more than a handful of adds
+const MAX_KNOWN_VALUE: i32 = 1024;
+
+/// Evaluate the start of the program on the zeroed tape, where the value of every cell
+/// is known, and write out only what it leaves behind: `++[-]>+++` becomes a single add to
+/// the second cell and a move, and `[-]`, scans and multiply loops on a cell known to be
+/// zero disappear, as do loops that start on one.
+///
+/// The cells are written out before anything that looks at them, such as `.`, and where
+/// the evaluation stops: at `,`, at a loop or scan on a nonzero cell, when the data
+/// pointer would move left of the first cell, or when a value grows past
+/// [`MAX_KNOWN_VALUE`]. Values are tracked without wrapping, so they come out the same
+/// whatever the cell width.
+fn known_start(ops: Vec<(Op, usize)>) -> Vec<(Op, usize)> {
+    let mut folded = Vec::with_capacity(ops.len());
+    let mut tape = KnownTape::default();
+    let mut ops = ops.into_iter();
+
+    while let Some((op, pos)) = ops.next() {
+        let dp = tape.dp;
+        let cell = tape.get(dp);
+
+        let known = match op {
+            Op::Add(delta) => tape.add(dp, i32::from(delta), pos),
+            Op::AddAtOffset { offset, delta } => tape.add(dp + offset, i32::from(delta), pos),
+            Op::AddMul { offset, factor } => tape.add(dp + offset, cell * i32::from(factor), pos),
+            Op::SetZero => tape.add(dp, -cell, pos),
+            Op::SetConst(value) => tape.add(dp, i32::from(value) - cell, pos),
+            Op::Move(delta) if dp + delta >= 0 => {
+                tape.dp += delta;
+                tape.moved.get_or_insert(pos);
+                true
+            }
+            Op::ScanRight | Op::ScanLeft => cell == 0,
+            Op::JumpIfZero if cell == 0 => {
+                // Skip the body up to the matching `]`
+                let mut depth = 1;
+                for (op, _) in ops.by_ref() {
+                    match op {
+                        Op::JumpIfZero => depth += 1,
+                        Op::JumpIfNonZero if depth == 1 => break,
+                        Op::JumpIfNonZero => depth -= 1,
+                        _ => {}
+                    }
+                }
+                true
+            }
+            Op::Output | Op::OutputRepeat(_) | Op::DebugDump => {
+                tape.write_out(&mut folded);
+                folded.push((op, pos));
+                continue;
+            }
+            _ => false,
+        };
+
+        if !known {
+            tape.write_out(&mut folded);
+            folded.push((op, pos));
+            folded.extend(ops);
+            return folded;
+        }
+    }
+
+    tape.write_out(&mut folded);
+    folded
+}
+
+/// The cells and data pointer at the start of the program, for [`known_start`].
+#[derive(Default)]
+struct KnownTape {
+    /// Value of every cell touched so far, keyed by index; the rest are zero
+    cells: BTreeMap<isize, i32>,
+    dp: isize,
+    /// Cells and data pointer as the ops written out so far leave them
+    written: BTreeMap<isize, i32>,
+    written_dp: isize,
+    /// Position of the first op to change each cell since the last write out
+    changed: BTreeMap<isize, usize>,
+    /// Position of the first move since the last write out
+    moved: Option<usize>,
+}
+
+impl KnownTape {
+    fn get(&self, index: isize) -> i32 {
+        self.cells.get(&index).copied().unwrap_or(0)
+    }
+
+    /// Add `delta` to the cell at `index` for the op at `pos`, returning `false` without
+    /// changing it if the value would grow too large to track.
+    fn add(&mut self, index: isize, delta: i32, pos: usize) -> bool {
+        let value = self.get(index) + delta;
+        if value.abs() > MAX_KNOWN_VALUE {
+            return false;
+        }
+
+        self.cells.insert(index, value);
+        self.changed.entry(index).or_insert(pos);
+        true
+    }
+
+    /// Push the adds and move that bring the cells and data pointer from what was last
+    /// written out to their current values, each at the position of the first op that
+    /// changed it and in that order, so an error points at the same command.
+    fn write_out(&mut self, ops: &mut Vec<(Op, usize)>) {
+        // Cells to write out by index, with `None` for the move
+        let mut changes: Vec<(usize, Option<isize>)> = std::mem::take(&mut self.changed)
+            .into_iter()
+            .map(|(index, pos)| (pos, Some(index)))
+            .collect();
+        if let Some(pos) = self.moved.take().filter(|_| self.dp != self.written_dp) {
+            changes.push((pos, None));
+        }
+        changes.sort_by_key(|&(pos, _)| pos);
+
+        let mut dp = self.written_dp;
+        for (pos, change) in changes {
+            let Some(index) = change else {
+                ops.push((Op::Move(self.dp - dp), pos));
+                dp = self.dp;
+                continue;
+            };
+
+            let mut delta = self.get(index) - self.written.get(&index).copied().unwrap_or(0);
+            while delta != 0 {
+                let step = delta.clamp(i8::MIN.into(), i8::MAX.into());
+                let op = match index - dp {
+                    0 => Op::Add(step as i8),
+                    offset => Op::AddAtOffset {
+                        offset,
+                        delta: step as i8,
+                    },
+                };
+                ops.push((op, pos));
+                delta -= step;
+            }
+        }
+
+        self.written = self.cells.clone();
+        self.written_dp = self.dp;
+    }
+}
+
+/// Rewrite straight-line runs of [`Op::Add`] and [`Op::Move`], such as `>+>+>+<<<`, as
+/// [`Op::AddAtOffset`]s relative to where the run starts followed by a single `Move`.
+///
+/// Runs are only rewritten when that makes them shorter. Deltas for the same cell are
+/// combined as long as they fit in an `i8`, like in [`fold_runs`].
+fn offset_blocks(ops: Vec<(Op, usize)>) -> Vec<(Op, usize)> {
+    let mut rewritten = Vec::with_capacity(ops.len());
+    let mut block = vec![];
+
+    for (op, pos) in ops {
+        if matches!(op, Op::Add(_) | Op::Move(_)) {
+            block.push((op, pos));
+            continue;
+        }
+
+        flush_block(&mut block, &mut rewritten);
+        rewritten.push((op, pos));
+    }
+    flush_block(&mut block, &mut rewritten);
+
+    rewritten
+}
+
+/// Move the ops in `block` onto the end of `ops`, as offset adds if that's shorter.
+fn flush_block(block: &mut Vec<(Op, usize)>, ops: &mut Vec<(Op, usize)>) {
+    let mut offset = 0;
+    // (offset, delta, position) of each add, in the order the cells were first touched
+    let mut adds: Vec<(isize, i8, usize)> = vec![];
+    // Position of the first move, which the final move takes
+    let mut move_pos = None;
+
+    for (op, pos) in block.iter() {
+        match op {
+            Op::Add(delta) => match adds.iter_mut().rev().find(|add| add.0 == offset) {
+                Some(add) if add.1.checked_add(*delta).is_some() => add.1 += delta,
+                _ => adds.push((offset, *delta, *pos)),
+            },
+            Op::Move(delta) => {
+                offset += delta;
+                move_pos.get_or_insert(*pos);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    adds.retain(|add| add.1 != 0);
+    let len = adds.len() + usize::from(offset != 0);
+    if len >= block.len() {
+        ops.append(block);
+        return;
+    }
+
+    for (offset, delta, pos) in adds {
+        let op = match offset {
+            0 => Op::Add(delta),
+            offset => Op::AddAtOffset { offset, delta },
+        };
+        ops.push((op, pos));
+    }
+    if let Some(pos) = move_pos.filter(|_| offset != 0) {
+        ops.push((Op::Move(offset), pos));
+    }
+    block.clear();
+}
+
+/// Net delta applied to each cell, keyed by offset from the loop's cell, if `body` is a
+/// multiply loop body. The loop's own cell is not included.
+fn multiply_factors(body: &[Op]) -> Option<BTreeMap<isize, i8>> {
+    let mut offset = 0;
+    let mut deltas = BTreeMap::new();
+
+    for op in body {
+        match op {
+            Op::Add(delta) => {
+                let total: &mut i8 = deltas.entry(offset).or_insert(0);
+                *total = total.checked_add(*delta)?;
+            }
+            Op::Move(delta) => offset += delta,
+            _ => return None,
+        }
+    }
+
+    if offset != 0 || deltas.remove(&0) != Some(-1) {
+        return None;
+    }
+
+    deltas.retain(|_, factor| *factor != 0);
+    Some(deltas)
+}
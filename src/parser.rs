@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::{nest, Op};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A `[` that is never closed by a matching `]`.
+    UnmatchedOpen { pos: usize, location: Location },
+    /// A `]` with no open `[` to match.
+    UnmatchedClose { pos: usize, location: Location },
+    /// A `[` nested more than `limit` loops deep.
+    NestingTooDeep {
+        pos: usize,
+        location: Location,
+        limit: usize,
+    },
+    /// A byte that is neither a command nor whitespace, in strict mode.
+    StrayByte {
+        pos: usize,
+        location: Location,
+        byte: u8,
+    },
+}
+
+/// Deepest loop nesting accepted by [`parse`] and [`parse_extended`].
+pub const DEFAULT_MAX_LOOP_DEPTH: usize = 4096;
+
+/// Human readable position of a byte offset in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in characters, with each invalid UTF-8 sequence counting as
+    /// one.
+    pub column: usize,
+    /// The full source line containing the position, without its line terminator.
+    /// Invalid UTF-8 is replaced with U+FFFD.
+    pub text: String,
+    /// Name of the file the source came from, if it was one of several.
+    pub file: Option<String>,
+}
+
+impl Location {
+    /// Locate the byte offset `pos` in `src`.
+    pub fn new(src: impl AsRef<[u8]>, pos: usize) -> Self {
+        let src = src.as_ref();
+        let line_start = src[..pos]
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |i| i + 1);
+        let line_end = src[pos..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(src.len(), |i| pos + i);
+
+        // Sources needn't be UTF-8, but counting and showing characters is friendlier
+        // than counting bytes when they are
+        let text = String::from_utf8_lossy(&src[line_start..line_end]);
+        Self {
+            line: src[..pos].iter().filter(|&&byte| byte == b'\n').count() + 1,
+            column: String::from_utf8_lossy(&src[line_start..pos])
+                .chars()
+                .count()
+                + 1,
+            text: text.trim_end_matches('\r').to_string(),
+            file: None,
+        }
+    }
+
+    /// Name the file the position is in, for sources joined from several files.
+    pub fn in_file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+}
+
+impl ParseError {
+    /// Byte offset in the source the error points at.
+    pub fn pos(&self) -> usize {
+        match self {
+            ParseError::UnmatchedOpen { pos, .. }
+            | ParseError::UnmatchedClose { pos, .. }
+            | ParseError::NestingTooDeep { pos, .. }
+            | ParseError::StrayByte { pos, .. } => *pos,
+        }
+    }
+
+    /// Report the error at `location` instead, e.g. within the file its position came
+    /// from when the source was joined from several.
+    pub fn set_location(&mut self, location: Location) {
+        match self {
+            ParseError::UnmatchedOpen { location: old, .. }
+            | ParseError::UnmatchedClose { location: old, .. }
+            | ParseError::NestingTooDeep { location: old, .. }
+            | ParseError::StrayByte { location: old, .. } => *old = location,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (bracket, location) = match self {
+            ParseError::UnmatchedOpen { location, .. } => ('[', location),
+            ParseError::UnmatchedClose { location, .. } => (']', location),
+            ParseError::NestingTooDeep {
+                location, limit, ..
+            } => {
+                return write!(f, "loop nesting exceeds limit of {} at {}", limit, location);
+            }
+            ParseError::StrayByte { location, byte, .. } if byte.is_ascii_graphic() => {
+                return write!(f, "stray character '{}' at {}", *byte as char, location);
+            }
+            ParseError::StrayByte { location, byte, .. } => {
+                return write!(f, "stray byte 0x{:02X} at {}", byte, location);
+            }
+        };
+
+        write!(f, "unmatched '{}' at {}", bracket, location)
+    }
+}
+
+impl fmt::Display for Location {
+    /// Show the file, if any, line and column, then the source line with a caret under the column.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}, ", file)?;
+        }
+        writeln!(f, "line {}, column {}", self.line, self.column)?;
+        writeln!(f, "{}", self.text)?;
+        write!(f, "{:>width$}", "^", width = self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse Brainfuck source into a sequence of ops, checking that every `[` has a matching `]`.
+///
+/// The source is treated as bytes: everything other than the eight commands is a comment,
+/// whether or not it is valid UTF-8.
+pub fn parse(src: impl AsRef<[u8]>) -> Result<Vec<Op>, ParseError> {
+    parse_with_positions(src, false, false, DEFAULT_MAX_LOOP_DEPTH)
+        .map(|(operations, _)| operations)
+}
+
+/// Like [`parse`], but also recognize extension commands: `#` dumps the tape.
+pub fn parse_extended(src: impl AsRef<[u8]>) -> Result<Vec<Op>, ParseError> {
+    parse_with_positions(src, true, false, DEFAULT_MAX_LOOP_DEPTH).map(|(operations, _)| operations)
+}
+
+/// Like [`parse`], but return the program as a tree, with each loop an [`Op::Loop`]
+/// holding its body instead of a pair of jumps.
+///
+/// Loops can be moved, replaced and removed whole in the tree without keeping jumps
+/// balanced. [`lower`](crate::lower) turns it back into the flat ops the backends run.
+pub fn parse_tree(src: impl AsRef<[u8]>) -> Result<Vec<Op>, ParseError> {
+    parse(src).map(nest)
+}
+
+/// Parse `src`, with extension commands if `extensions` is set, and also return the
+/// source byte offset of each op.
+///
+/// With `strict` set, any byte other than a command or ASCII whitespace is a
+/// [`ParseError::StrayByte`] rather than a comment, which catches corrupted sources.
+///
+/// Loops nested more than `max_depth` deep are rejected, which keeps machine-generated
+/// programs from producing code the backends can't handle.
+pub fn parse_with_positions(
+    src: impl AsRef<[u8]>,
+    extensions: bool,
+    strict: bool,
+    max_depth: usize,
+) -> Result<(Vec<Op>, Vec<usize>), ParseError> {
+    let src = src.as_ref();
+    let mut operations = vec![];
+    let mut positions = vec![];
+    // Source byte offset of each `[` still waiting for its `]`
+    let mut jump_op_stack = vec![];
+
+    for (pos, &byte) in src.iter().enumerate() {
+        match byte {
+            b'+' => operations.push(Op::Inc),
+            b'-' => operations.push(Op::Dec),
+            b'<' => operations.push(Op::MoveLeft),
+            b'>' => operations.push(Op::MoveRight),
+            b'.' => operations.push(Op::Output),
+            b',' => operations.push(Op::Input),
+            b'#' if extensions => operations.push(Op::DebugDump),
+            b'[' => {
+                if jump_op_stack.len() == max_depth {
+                    return Err(ParseError::NestingTooDeep {
+                        pos,
+                        location: Location::new(src, pos),
+                        limit: max_depth,
+                    });
+                }
+                jump_op_stack.push(pos);
+                operations.push(Op::JumpIfZero);
+            }
+            b']' => {
+                if jump_op_stack.pop().is_none() {
+                    return Err(ParseError::UnmatchedClose {
+                        pos,
+                        location: Location::new(src, pos),
+                    });
+                }
+                operations.push(Op::JumpIfNonZero);
+            }
+            _ if strict && !byte.is_ascii_whitespace() => {
+                return Err(ParseError::StrayByte {
+                    pos,
+                    location: Location::new(src, pos),
+                    byte,
+                });
+            }
+            _ => {
+                // Brainfuck ignores all other bytes
+                continue;
+            }
+        }
+
+        positions.push(pos);
+    }
+
+    if let Some(&pos) = jump_op_stack.last() {
+        return Err(ParseError::UnmatchedOpen {
+            pos,
+            location: Location::new(src, pos),
+        });
+    }
+
+    Ok((operations, positions))
+}
@@ -0,0 +1,205 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{op::jump_table, Op};
+
+/// Counters collected while the interpreter runs a program.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RunStats {
+    /// Total number of ops executed.
+    pub ops_executed: u64,
+    /// Number of executions of each kind of op, keyed by [`Op::name`].
+    pub op_counts: BTreeMap<&'static str, u64>,
+    /// Number of `[` and `]` ops that jumped instead of falling through.
+    pub jumps_taken: u64,
+    /// Highest cell index the data pointer reached (the tape high-water mark).
+    pub max_dp: usize,
+    /// Every loop that ran, hottest first.
+    pub loops: Vec<LoopProfile>,
+}
+
+/// How much work one loop of the program did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProfile {
+    /// Index of the loop's `[` op.
+    pub start: usize,
+    /// Index of the loop's `]` op.
+    pub end: usize,
+    /// Number of times the loop body ran to its `]`.
+    pub iterations: u64,
+    /// Number of ops executed from the `[` to the `]`, including nested loops.
+    pub ops_executed: u64,
+}
+
+impl RunStats {
+    /// Build stats from the number of times each op in `ops` was executed.
+    pub(crate) fn new(ops: &[Op], executions: &[u64], jumps_taken: u64, max_dp: usize) -> Self {
+        let mut op_counts = BTreeMap::new();
+        for (op, count) in ops.iter().zip(executions) {
+            if *count > 0 {
+                *op_counts.entry(op.name()).or_insert(0) += count;
+            }
+        }
+
+        Self {
+            ops_executed: executions.iter().sum(),
+            op_counts,
+            jumps_taken,
+            max_dp,
+            loops: profile_loops(ops, executions),
+        }
+    }
+
+    /// Number of iterations of every loop that ran, keyed by the index of its `[` op.
+    pub fn loop_iterations(&self) -> BTreeMap<usize, u64> {
+        self.loops
+            .iter()
+            .map(|profile| (profile.start, profile.iterations))
+            .collect()
+    }
+
+    /// Every loop that ran, most iterations first.
+    pub fn hot_loops(&self) -> Vec<&LoopProfile> {
+        let mut loops: Vec<_> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.iterations.cmp(&a.iterations).then(a.start.cmp(&b.start)));
+        loops
+    }
+}
+
+/// Measurements of a program taken from its ops alone, without running it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// Total number of ops.
+    pub ops: usize,
+    /// Number of loops.
+    pub loops: usize,
+    /// Deepest nesting of loops, 0 for a program without any.
+    pub max_depth: usize,
+    /// Number of times each command appears, keyed by the command.
+    pub command_counts: BTreeMap<char, usize>,
+    /// Longest run of one repeated `+`, `-`, `<` or `>` as the command and its length.
+    pub longest_run: Option<(char, usize)>,
+}
+
+/// Commands in the order [`ProgramStats`] lists them
+const COMMANDS: &str = "+-<>.,[]#";
+
+impl ProgramStats {
+    /// Measure `ops` as they come from the parser, one per command. Ops the optimizer
+    /// introduces don't stand for a single command and are only counted in the total.
+    pub fn new(ops: &[Op]) -> Self {
+        let mut stats = Self {
+            ops: ops.len(),
+            ..Self::default()
+        };
+        let mut depth = 0;
+        let mut run: Option<(char, usize)> = None;
+
+        for op in ops {
+            let Some(command) = command(op) else {
+                run = None;
+                continue;
+            };
+            *stats.command_counts.entry(command).or_insert(0) += 1;
+
+            match op {
+                Op::JumpIfZero => {
+                    stats.loops += 1;
+                    depth += 1;
+                    stats.max_depth = stats.max_depth.max(depth);
+                }
+                Op::JumpIfNonZero => depth -= 1,
+                _ => {}
+            }
+
+            run = match run {
+                Some((previous, length)) if previous == command => Some((command, length + 1)),
+                _ if "+-<>".contains(command) => Some((command, 1)),
+                _ => None,
+            };
+            if let Some((_, length)) = run {
+                if stats
+                    .longest_run
+                    .is_none_or(|(_, longest)| length > longest)
+                {
+                    stats.longest_run = run;
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+/// The command an op from the parser stands for.
+fn command(op: &Op) -> Option<char> {
+    match op {
+        Op::Inc => Some('+'),
+        Op::Dec => Some('-'),
+        Op::MoveLeft => Some('<'),
+        Op::MoveRight => Some('>'),
+        Op::Output => Some('.'),
+        Op::Input => Some(','),
+        Op::JumpIfZero => Some('['),
+        Op::JumpIfNonZero => Some(']'),
+        Op::DebugDump => Some('#'),
+        _ => None,
+    }
+}
+
+/// Profile every loop in `ops` that ran, sorted by the ops executed inside it.
+fn profile_loops(ops: &[Op], executions: &[u64]) -> Vec<LoopProfile> {
+    let table = jump_table(ops);
+
+    let mut loops: Vec<LoopProfile> = ops
+        .iter()
+        .enumerate()
+        .filter(|&(start, op)| matches!(op, Op::JumpIfZero) && executions[start] > 0)
+        .map(|(start, _)| {
+            let end = table[start] - 1;
+            LoopProfile {
+                start,
+                end,
+                iterations: executions[end],
+                ops_executed: executions[start..=end].iter().sum(),
+            }
+        })
+        .collect();
+
+    loops.sort_by(|a, b| {
+        b.ops_executed
+            .cmp(&a.ops_executed)
+            .then(a.start.cmp(&b.start))
+    });
+    loops
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<17}{}", "ops executed:", self.ops_executed)?;
+        for (name, count) in &self.op_counts {
+            writeln!(f, "  {:<15}{}", name, count)?;
+        }
+        writeln!(f, "{:<17}{}", "jumps taken:", self.jumps_taken)?;
+        write!(f, "{:<17}{}", "max dp:", self.max_dp)
+    }
+}
+
+impl fmt::Display for ProgramStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<17}{}", "ops:", self.ops)?;
+        writeln!(f, "{:<17}{}", "loops:", self.loops)?;
+        writeln!(f, "{:<17}{}", "max depth:", self.max_depth)?;
+        writeln!(f, "commands:")?;
+        for command in COMMANDS.chars() {
+            let count = self.command_counts.get(&command).copied().unwrap_or(0);
+            // `#` is only a command with extensions on
+            if command != '#' || count > 0 {
+                writeln!(f, "  {:<15}{}", command, count)?;
+            }
+        }
+        match self.longest_run {
+            Some((command, length)) => write!(f, "{:<17}{} of {}", "longest run:", length, command),
+            None => write!(f, "{:<17}none", "longest run:"),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+
+use brainphoque::{
+    emit_bytecode, load_bytecode, optimize, parse, parse_extended, BytecodeError, Op,
+    BYTECODE_VERSION,
+};
+
+fn round_trip(ops: &[Op]) -> Vec<Op> {
+    load_bytecode(&emit_bytecode(ops)).unwrap()
+}
+
+#[test]
+fn loaded_bytecode_matches_the_parsed_program() {
+    for name in ["binary", "cat", "hello", "multiply"] {
+        let path = format!("{}/tests/programs/{}.bf", env!("CARGO_MANIFEST_DIR"), name);
+        let ops = optimize(parse(fs::read(path).unwrap()).unwrap());
+        assert_eq!(
+            format!("{:?}", round_trip(&ops)),
+            format!("{:?}", ops),
+            "{}",
+            name
+        );
+    }
+
+    // Every op, with operands at the edges of their range
+    let ops = vec![
+        Op::Add(i8::MIN),
+        Op::Move(i32::MAX as isize),
+        Op::Move(-(i32::MAX as isize)),
+        Op::Move(-64),
+        Op::Move(64),
+        Op::AddMul {
+            offset: -1,
+            factor: i8::MAX,
+        },
+        Op::AddAtOffset {
+            offset: 200,
+            delta: -1,
+        },
+        Op::OutputRepeat(i32::MAX as usize),
+        Op::SetConst(-3),
+        Op::JumpIfZero,
+        Op::ScanRight,
+        Op::ScanLeft,
+        Op::SetZero,
+        Op::JumpIfNonZero,
+    ];
+    assert_eq!(format!("{:?}", round_trip(&ops)), format!("{:?}", ops));
+
+    let ops = parse_extended("+-<>.,[]#").unwrap();
+    assert_eq!(format!("{:?}", round_trip(&ops)), format!("{:?}", ops));
+}
+
+#[test]
+fn corrupted_bytecode_is_rejected() {
+    let bytes = emit_bytecode(&parse("+[->+<]").unwrap());
+
+    assert_eq!(
+        load_bytecode(b"+[->+<]").unwrap_err(),
+        BytecodeError::NotBytecode
+    );
+
+    let mut newer = bytes.clone();
+    newer[4] = BYTECODE_VERSION + 1;
+    assert_eq!(
+        load_bytecode(&newer).unwrap_err(),
+        BytecodeError::UnsupportedVersion {
+            version: BYTECODE_VERSION + 1
+        }
+    );
+
+    assert_eq!(
+        load_bytecode(&bytes[..bytes.len() - 1]).unwrap_err(),
+        BytecodeError::Truncated
+    );
+
+    let mut unknown = bytes.clone();
+    unknown[9] = 0xFF;
+    assert_eq!(
+        load_bytecode(&unknown).unwrap_err(),
+        BytecodeError::UnknownOp { pos: 9, tag: 0xFF }
+    );
+
+    // A `]` where the `[` was
+    let mut unbalanced = bytes.clone();
+    unbalanced[10] = 7;
+    assert_eq!(
+        load_bytecode(&unbalanced).unwrap_err(),
+        BytecodeError::UnbalancedJumps
+    );
+
+    // Operands past what the backends run, which would otherwise exhaust memory
+    let huge_repeat = emit_bytecode(&[Op::OutputRepeat(1 << 40)]);
+    assert_eq!(
+        load_bytecode(&huge_repeat).unwrap_err(),
+        BytecodeError::OperandOutOfRange { pos: 10 }
+    );
+    let huge_move = emit_bytecode(&[Op::Move(-(1 << 40))]);
+    assert_eq!(
+        load_bytecode(&huge_move).unwrap_err(),
+        BytecodeError::OperandOutOfRange { pos: 10 }
+    );
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(
+        load_bytecode(&trailing).unwrap_err(),
+        BytecodeError::TrailingBytes { pos: bytes.len() }
+    );
+}
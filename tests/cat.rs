@@ -0,0 +1,119 @@
+//! `cat` programs must stop at the end of input instead of looping on the last byte.
+
+use std::{
+    io::{self, Read, Write},
+    process::{Command, Stdio},
+};
+
+use brainphoque::{
+    optimize, parse, ByteFilter, EofPolicy, FilterReader, FilterWriter, Interpreter,
+    DEFAULT_TAPE_SIZE,
+};
+
+fn interpret(src: &str, eof_policy: EofPolicy) -> Vec<u8> {
+    let mut output = vec![];
+    Interpreter::new(
+        optimize(parse(src).unwrap()),
+        DEFAULT_TAPE_SIZE,
+        &b"hello"[..],
+        &mut output,
+    )
+    .eof_policy(eof_policy)
+    .max_steps(Some(10_000))
+    .run()
+    .unwrap();
+    output
+}
+
+#[test]
+fn each_eof_policy_has_a_cat_that_ends() {
+    assert_eq!(interpret(",[.,]", EofPolicy::Zero), b"hello");
+    assert_eq!(interpret(",[.[-],]", EofPolicy::Unchanged), b"hello");
+    assert_eq!(interpret(",+[-.,+]", EofPolicy::NegativeOne), b"hello");
+}
+
+#[test]
+fn cat_echoes_stdin() {
+    let mut modes = vec!["interpret"];
+    if brainphoque::JIT_SUPPORTED {
+        modes.push("jit");
+    }
+
+    for mode in modes {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+            .args(["--mode", mode, "-e", ",[.,]"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"hello").unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{} failed", mode);
+        assert_eq!(output.stdout, b"hello", "{} output", mode);
+    }
+}
+
+/// A reader that hands out one byte per read, so a `\r\n` arrives split in two.
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some((&byte, rest)) = self.0.split_first() else {
+            return Ok(0);
+        };
+        buf[0] = byte;
+        self.0 = rest;
+        Ok(1)
+    }
+}
+
+#[test]
+fn filtered_cat_translates_line_endings() {
+    let input = b"one\r\ntwo\rthree\r\n\r";
+    let mut output = vec![];
+    Interpreter::new(
+        parse(",[.,]").unwrap(),
+        DEFAULT_TAPE_SIZE,
+        FilterReader::new(Trickle(input), &[ByteFilter::CrlfToLf]),
+        FilterWriter::new(&mut output, &[ByteFilter::Uppercase, ByteFilter::LfToCrlf]),
+    )
+    .run()
+    .unwrap();
+
+    assert_eq!(output, b"ONE\r\nTWO\rTHREE\r\n\r");
+
+    // A `\r` held back at the end of the output is written when the writer goes
+    let mut output = vec![];
+    let mut writer = FilterWriter::new(&mut output, &[ByteFilter::CrlfToLf]);
+    writer.write_all(b"a\r\nb\r").unwrap();
+    drop(writer);
+    assert_eq!(output, b"a\nb\r");
+}
+
+#[test]
+fn cat_filters_its_input_from_the_command_line() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args([
+            "--mode",
+            "interpret",
+            "--input-filter",
+            "crlf-to-lf",
+            "-e",
+            ",[.,]",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"a\r\nb\r\n")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a\nb\n");
+}
@@ -0,0 +1,416 @@
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn help_and_version_exit_successfully() {
+    for flag in ["-h", "--help"] {
+        let output = run(&[flag]);
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("--mode MODE"));
+    }
+
+    for flag in ["-V", "--version"] {
+        let output = run(&[flag]);
+        assert!(output.status.success());
+        assert_eq!(
+            output.stdout,
+            format!("brainphoque {}\n", env!("CARGO_PKG_VERSION")).as_bytes()
+        );
+    }
+}
+
+#[test]
+fn unknown_options_are_rejected() {
+    let output = run(&["--bogus"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unknown option '--bogus'"));
+}
+
+#[test]
+fn no_run_prints_ops_without_reading_input() {
+    let mut modes = vec!["interpret"];
+    if brainphoque::JIT_SUPPORTED {
+        modes.push("jit");
+    }
+
+    for mode in modes {
+        let output = run(&[
+            "--mode",
+            mode,
+            "--no-run",
+            "--dump-ops",
+            "--input",
+            "does-not-exist",
+            "-e",
+            ",.",
+        ]);
+        assert!(output.status.success(), "{} failed", mode);
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("Input"));
+    }
+}
+
+#[test]
+fn backends_exit_with_the_first_cell_only_when_asked() {
+    let mut modes = vec!["interpret"];
+    if brainphoque::JIT_SUPPORTED {
+        modes.push("jit");
+    }
+
+    for mode in modes {
+        let output = run(&["--mode", mode, "-e", "+++"]);
+        assert_eq!(output.status.code(), Some(0), "{}", mode);
+
+        let output = run(&["--mode", mode, "--exit-with-cell", "-e", "+++>++"]);
+        assert_eq!(output.status.code(), Some(3), "{}", mode);
+    }
+}
+
+#[test]
+fn repeated_output_prints_hello() {
+    // The doubled `l` is a run of output, written by the JIT in one go
+    let src = format!(
+        "{}.{}.{}..{}.",
+        "+".repeat(72),
+        "+".repeat(29),
+        "+".repeat(7),
+        "+".repeat(3)
+    );
+    let ops = brainphoque::optimize(brainphoque::parse(&src).unwrap());
+    assert!(ops
+        .iter()
+        .any(|op| matches!(op, brainphoque::Op::OutputRepeat(2))));
+
+    let mut modes = vec!["interpret"];
+    if brainphoque::JIT_SUPPORTED {
+        modes.push("jit");
+    }
+
+    for mode in modes {
+        let output = run(&["--mode", mode, "-e", &src]);
+        assert!(output.status.success(), "{} failed", mode);
+        assert_eq!(output.stdout, b"Hello", "{}", mode);
+    }
+}
+
+#[test]
+fn tape_overflow_points_at_the_command() {
+    let output = run(&["--mode", "interpret", "-c", "3", "-e", "+\n >>>+"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("tape overflow"), "{}", stderr);
+    assert!(stderr.contains("at line 2, column 2"), "{}", stderr);
+}
+
+#[test]
+fn pointer_underflow_points_at_the_command() {
+    let output = run(&["--mode", "interpret", "-e", "+\n><<"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("pointer underflow"), "{}", stderr);
+    assert!(stderr.contains("at line 2, column 3"), "{}", stderr);
+}
+
+#[test]
+fn hot_loops_lists_the_most_iterated_loop_first() {
+    let output = run(&[
+        "--mode",
+        "interpret",
+        "--hot-loops",
+        "-O0",
+        "-e",
+        "+++[->>>>>>>>+<<<<<<<<]\n+++++[-]",
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(lines[0], "hot loops:", "{}", stderr);
+    assert!(
+        lines[1].contains("2:6") && lines[1].contains("5 iterations"),
+        "{}",
+        stderr
+    );
+    assert!(
+        lines[2].contains("1:4") && lines[2].contains("3 iterations"),
+        "{}",
+        stderr
+    );
+}
+
+/// The committed blobs pin the code generated for a program without I/O, which is the
+/// same on every OS. Regenerate one after an intended codegen change with
+/// `brainphoque --no-run --dump-machine-code tests/machine_code/ARCH.bin -e '++[->+++<]>-'`.
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+#[test]
+fn dumped_machine_code_matches_the_golden_blob() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("machine_code.bin");
+    let output = run(&[
+        "--no-run",
+        "--dump-machine-code",
+        path.to_str().unwrap(),
+        "-e",
+        "++[->+++<]>-",
+    ]);
+    assert!(output.status.success());
+
+    let golden = std::fs::read(format!(
+        "{}/tests/machine_code/{}.bin",
+        env!("CARGO_MANIFEST_DIR"),
+        std::env::consts::ARCH
+    ))
+    .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), golden);
+}
+
+#[test]
+fn dump_tape_marks_the_data_pointer() {
+    let output = run(&["--dump-tape", "-e", "+++>>-<"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "0:    3  [  0]  255\n"
+    );
+}
+
+#[test]
+fn start_offset_leaves_room_to_move_left() {
+    let output = run(&["--start-offset", "1", "-e", "<."]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [0]);
+
+    let output = run(&["--start-offset", "2", "--dump-tape", "-e", "<+"]);
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "0:  0  [1]  0\n");
+
+    let output = run(&["-c", "3", "--start-offset", "3", "-e", "+"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--start-offset must be less than the 3 cells"));
+}
+
+#[test]
+fn input_string_is_the_program_input() {
+    let output = run(&["--input-string", "ab", "-e", ",.,."]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ab");
+
+    let output = run(&["--input", "in.txt", "--input-string", "ab", "-e", ",."]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("only one of --input or --input-string"));
+}
+
+#[test]
+fn repl_keeps_the_tape_between_lines() {
+    use std::{io::Write, process::Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .arg("--repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The loop spans two lines and only runs once it's closed; the stray `]` is
+    // reported without losing the tape
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"+++\n.\n>++[\n-<+>]<.\n]\n+.\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3, 5, 6]);
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unmatched ']'"));
+}
+
+#[test]
+fn jit_echoes_its_input() {
+    use std::{io::Write, process::Stdio};
+
+    if !brainphoque::JIT_SUPPORTED {
+        return;
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(["--mode", "jit", "-e", ",.,.,."])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"ok").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    // End of input stores 0
+    assert_eq!(output.stdout, b"ok\0");
+}
+
+#[test]
+fn failed_reads_stop_the_program() {
+    let mut modes = vec!["interpret"];
+    if brainphoque::JIT_SUPPORTED {
+        modes.push("jit");
+    }
+
+    // Reading a directory fails rather than returning end of input
+    let dir = env!("CARGO_MANIFEST_DIR");
+    for mode in modes {
+        let output = run(&["--mode", mode, "--input", dir, "-e", "+[,]"]);
+        assert!(!output.status.success(), "{}", mode);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("I/O error: Is a directory"),
+            "{}: {}",
+            mode,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn closed_pipe_ends_the_program_quietly() {
+    use std::process::Stdio;
+
+    let mut runs = vec![vec!["--mode", "interpret"], vec!["--emit", "c"]];
+    if brainphoque::JIT_SUPPORTED {
+        runs.push(vec!["--mode", "jit"]);
+    }
+
+    for args in runs {
+        // Prints forever unless the closed pipe stops it
+        let mut child = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+            .args(&args)
+            .args(["-e", "+[.]"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+
+        assert!(output.status.success(), "{:?}", args);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "", "{:?}", args);
+    }
+}
+
+#[test]
+fn count_only_measures_the_program_without_running_it() {
+    // Running would wait for input that never comes
+    let output = run(&[
+        "--count-only",
+        "--input",
+        "missing.txt",
+        "-e",
+        "+++[>,[-]<-]>>>>",
+    ]);
+    assert!(output.status.success());
+
+    let table = String::from_utf8(output.stdout).unwrap();
+    for line in [
+        "ops:             16",
+        "loops:           2",
+        "max depth:       2",
+        "  +              3",
+        "  ,              1",
+        "longest run:     4 of >",
+    ] {
+        assert!(table.contains(line), "no '{}' in\n{}", line, table);
+    }
+}
+
+#[test]
+fn output_mode_decimal_prints_cell_values() {
+    let src = "+".repeat(65) + ".";
+
+    let output = run(&["--output-mode", "decimal", "-e", &src]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"65 ");
+
+    let output = run(&["--output-mode", "raw", "-e", &src]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+}
+
+#[test]
+fn program_files_are_joined_in_order() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("program_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let open = dir.join("open.bf");
+    let close = dir.join("close.bf");
+    let stray = dir.join("stray.bf");
+    std::fs::write(&open, "++++++++[>++++++++").unwrap();
+    std::fs::write(&close, "<-]>+.\n").unwrap();
+    std::fs::write(&stray, "comment\n ]").unwrap();
+
+    let [open, close, stray] = [open, close, stray].map(|path| path.to_str().unwrap().to_owned());
+    let output = run(&["--mode", "interpret", &open, &close]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"A");
+
+    // Errors point into the file they're in
+    let output = run(&[&open, &close, &stray]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("unmatched ']' at {}, line 2, column 2", stray)),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn bytecode_runs_like_the_source_it_came_from() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("bytecode");
+    std::fs::create_dir_all(&dir).unwrap();
+    let bytecode = dir.join("hello.bfc");
+    let bytecode = bytecode.to_str().unwrap();
+    let hello = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/hello.bf");
+
+    let output = run(&["--emit", "bytecode", bytecode, hello]);
+    assert!(output.status.success());
+    let output = run(&["--mode", "interpret", "--run-bytecode", bytecode]);
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/programs/hello.out"
+        ))
+        .unwrap()
+    );
+
+    // Without a source, errors point at the op
+    let overflow = dir.join("overflow.bfc");
+    let overflow = overflow.to_str().unwrap();
+    run(&["--emit", "bytecode", overflow, "-e", "+[>+]"]);
+    let output = run(&["--mode", "interpret", "-c", "3", "--run-bytecode", overflow]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("at op "), "{}", stderr);
+
+    let output = run(&["--run-bytecode", hello]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("not a bytecode file"));
+}
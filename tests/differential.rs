@@ -0,0 +1,203 @@
+//! Run random programs through the interpreter, unoptimized and optimized, and the JIT,
+//! and check that they all print the same bytes and leave the same value in the first
+//! cell.
+//!
+//! Programs come from a seeded generator, so a failure can be replayed: set
+//! `BRAINPHOQUE_SEED` to the seed it reports, and `BRAINPHOQUE_PROGRAMS` to run more or
+//! fewer programs than the default.
+
+#![cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use std::{
+    env,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use brainphoque::{
+    optimize_with_level, parse, ExecutionError, Interpreter, OptLevel, DEFAULT_TAPE_SIZE,
+};
+
+/// Cells a program may move away from its starting cell, which is this far from cell 0
+const MAX_OFFSET: usize = 4;
+/// Ops the interpreter runs before giving up on a program that may never finish
+const MAX_STEPS: u64 = 100_000;
+/// Input every program reads from
+const INPUT: &[u8] = b"differential";
+
+/// A xorshift64* generator, so a seed always gives the same programs.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as usize % n
+    }
+}
+
+/// Append up to `len` random commands to `src`, starting `offset` cells from the starting
+/// cell. Every loop body comes back to the cell it started on, so the data pointer never
+/// strays more than [`MAX_OFFSET`] cells. Returns the offset the commands end on.
+fn commands(rng: &mut Rng, src: &mut String, len: usize, depth: usize, mut offset: isize) -> isize {
+    let bound = MAX_OFFSET as isize;
+
+    for _ in 0..len {
+        match rng.below(10) {
+            0..=2 => src.push('+'),
+            3..=4 => src.push('-'),
+            5 if offset < bound => {
+                src.push('>');
+                offset += 1;
+            }
+            6 if offset > -bound => {
+                src.push('<');
+                offset -= 1;
+            }
+            7 => src.push('.'),
+            8 => src.push(','),
+            9 if depth < 3 => {
+                // Counting the cell down makes it likely the loop ends
+                src.push_str("[-");
+                let len = rng.below(8);
+                let end = commands(rng, src, len, depth + 1, offset);
+                let back = if end > offset { '<' } else { '>' };
+                src.extend(std::iter::repeat_n(back, end.abs_diff(offset)));
+                src.push(']');
+            }
+            _ => {}
+        }
+    }
+
+    offset
+}
+
+/// A random program that ends by printing the cells around its starting cell.
+fn program(rng: &mut Rng) -> String {
+    let mut src = ">".repeat(MAX_OFFSET);
+    let len = rng.below(40);
+    let offset = commands(rng, &mut src, len, 0, 0);
+
+    src.extend(std::iter::repeat_n(
+        '<',
+        (MAX_OFFSET as isize + offset) as usize,
+    ));
+    src.push_str(&".>".repeat(2 * MAX_OFFSET + 1));
+    src
+}
+
+/// Output and first cell of `src` under the interpreter, or `None` if it doesn't finish.
+fn interpret(src: &str, level: OptLevel) -> Option<(Vec<u8>, u8)> {
+    let ops = optimize_with_level(parse(src).unwrap(), level);
+    let mut interpreter = Interpreter::with_input_bytes(ops, INPUT).max_steps(Some(MAX_STEPS));
+
+    match interpreter.run() {
+        Ok(_) => Some((interpreter.output().to_vec(), interpreter.cell(0))),
+        Err(ExecutionError::StepLimitExceeded { .. }) => None,
+        Err(err) => panic!("{} failed: {}", src, err),
+    }
+}
+
+/// Output and first cell of `src` compiled by the JIT with the extra `options`, reading
+/// `input`. The JIT writes straight to the stdout file descriptor and so runs in a child
+/// process, which exits with the first cell.
+fn jit(src: &str, options: &[&str], input: &[u8]) -> (Vec<u8>, u8) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(["--mode", "jit", "--exit-with-cell"])
+        .args(options)
+        .args(["-e", src])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The program may finish without reading all of it
+    let _ = child.stdin.take().unwrap().write_all(input);
+    let output = child.wait_with_output().unwrap();
+
+    let Some(first_cell) = output.status.code() else {
+        panic!("{} failed: {:?}", src, output.status);
+    };
+    (output.stdout, first_cell as u8)
+}
+
+#[test]
+fn backends_agree_on_random_programs() {
+    let seed = env::var("BRAINPHOQUE_SEED").map_or(0x5EED, |seed| seed.parse().unwrap());
+    let count = env::var("BRAINPHOQUE_PROGRAMS").map_or(200, |count| count.parse().unwrap());
+    // xorshift never leaves 0
+    let mut rng = Rng(seed.max(1));
+    let mut finished = 0;
+
+    for _ in 0..count {
+        let src = program(&mut rng);
+        let Some(expected) = interpret(&src, OptLevel::O0) else {
+            continue;
+        };
+        finished += 1;
+
+        let context = format!("seed {}, program {}", seed, src);
+        assert_eq!(
+            interpret(&src, OptLevel::O2),
+            Some(expected.clone()),
+            "{}",
+            context
+        );
+        assert_eq!(jit(&src, &[], INPUT), expected, "{}", context);
+    }
+
+    assert!(finished > count / 2, "only {} programs finished", finished);
+}
+
+#[test]
+fn multiply_loops_on_a_zero_cell_at_the_tape_edge_touch_nothing() {
+    // The loop would add to the cell past the end of the tape, but input is empty, so
+    // its body never runs
+    const CELLS: usize = 4096;
+    let src = ">".repeat(CELLS - 1) + ",[->+<].";
+
+    let ops = optimize_with_level(parse(&src).unwrap(), OptLevel::O2);
+    let mut interpreter = Interpreter::new(ops, CELLS, &b""[..], vec![]);
+    interpreter.run().unwrap();
+    let expected = (interpreter.output().to_vec(), interpreter.cell(0));
+
+    let cells = CELLS.to_string();
+    for check in ["--safe-jit", "--guarded-tape"] {
+        assert_eq!(
+            jit(&src, &["--cells", &cells, check], b""),
+            expected,
+            "{}",
+            check
+        );
+    }
+}
+
+#[test]
+fn cells_never_written_read_as_zero() {
+    let src = ">.>>.<+.";
+    assert_eq!(interpret(src, OptLevel::O0), Some((vec![0, 0, 1], 0)));
+    assert_eq!(jit(src, &[], b""), (vec![0, 0, 1], 0));
+}
+
+#[test]
+fn backends_have_the_same_number_of_cells() {
+    // The last cell is on the tape for both
+    let last = DEFAULT_TAPE_SIZE - 1;
+    let src = ">".repeat(last) + "+." + &"<".repeat(last) + ".";
+    assert_eq!(interpret(&src, OptLevel::O0), Some((vec![1, 0], 0)));
+    assert_eq!(jit(&src, &[], b""), (vec![1, 0], 0));
+
+    // and the one after it is off it for both
+    let src = ">".repeat(DEFAULT_TAPE_SIZE) + "+";
+    let result = Interpreter::with_input_bytes(parse(&src).unwrap(), b"").run();
+    assert!(matches!(result, Err(ExecutionError::TapeOverflow { .. })));
+    let output = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(["--mode", "jit", "--safe-jit", "-e", &src])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
@@ -0,0 +1,290 @@
+//! Decode the machine code the JIT emits for each op with `llvm-mc` and check that it is
+//! the instructions the code generator's comments describe.
+//!
+//! Needs `llvm-mc`, and fails when it can't be found. Set `LLVM_MC` to use another
+//! binary.
+
+#![cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use std::{env, io, process::Command};
+
+use brainphoque::{JitCompiler, Op};
+
+/// Disassemble `code` for the host, one instruction per line with its operands separated
+/// by single spaces.
+fn disassemble(code: &[u8]) -> Vec<String> {
+    let llvm_mc = env::var("LLVM_MC").unwrap_or_else(|_| "llvm-mc".to_string());
+    let bytes: Vec<String> = code.iter().map(|byte| format!("{:#04x}", byte)).collect();
+
+    let mut command = Command::new(llvm_mc);
+    command
+        .arg("--disassemble")
+        .arg(format!("-triple={}", env::consts::ARCH));
+    // Intel syntax, to match the comments
+    #[cfg(target_arch = "x86_64")]
+    command.arg("-output-asm-variant=1");
+
+    let mut child = match command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            panic!("llvm-mc not found: install LLVM or set LLVM_MC to its path")
+        }
+        Err(err) => panic!("cannot run llvm-mc: {}", err),
+    };
+    io::Write::write_all(&mut child.stdin.take().unwrap(), bytes.join(" ").as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let instructions = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty() && !line.starts_with('.'))
+        .collect();
+    instructions
+}
+
+/// The machine code for each of `ops`, taken from the compiler's listing.
+fn op_code(ops: Vec<Op>, bounds_checks: bool) -> Vec<Vec<u8>> {
+    let names: Vec<String> = ops.iter().map(|op| format!("{:?}", op)).collect();
+    let listing = JitCompiler::new(ops)
+        .bounds_checks(bounds_checks)
+        .listing()
+        .unwrap();
+    let lines = listing
+        .lines()
+        .filter(|line| !line.contains("(prologue)") && !line.contains("(epilogue)"));
+
+    names
+        .iter()
+        .zip(lines)
+        .map(|(name, line)| {
+            let (_, bytes) = line.split_once(name.as_str()).unwrap();
+            bytes
+                .split_whitespace()
+                .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+/// Check that each op compiles to exactly the instructions listed with it.
+fn check(bounds_checks: bool, expected: Vec<(Op, &[&str])>) {
+    let (ops, instructions): (Vec<_>, Vec<_>) = expected.into_iter().unzip();
+    let names: Vec<String> = ops.iter().map(|op| format!("{:?}", op)).collect();
+
+    for ((name, code), instructions) in names
+        .iter()
+        .zip(op_code(ops, bounds_checks))
+        .zip(instructions)
+    {
+        assert_eq!(disassemble(&code), instructions, "{}", name);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn ops_decode_to_their_documented_instructions() {
+    check(
+        false,
+        vec![
+            (Op::Inc, &["inc byte ptr [rdi]"]),
+            (Op::Dec, &["dec byte ptr [rdi]"]),
+            (Op::MoveRight, &["inc rdi"]),
+            (Op::MoveLeft, &["dec rdi"]),
+            (Op::Add(5), &["add byte ptr [rdi], 5"]),
+            (Op::Move(300), &["add rdi, 300"]),
+            (Op::Move(-2), &["add rdi, -2"]),
+            (Op::SetZero, &["mov byte ptr [rdi], 0"]),
+            (Op::SetConst(7), &["mov byte ptr [rdi], 7"]),
+            (
+                Op::AddMul {
+                    offset: 2,
+                    factor: 3,
+                },
+                &[
+                    "cmp byte ptr [rdi], 0",
+                    "je 12",
+                    "movzx eax, byte ptr [rdi]",
+                    "imul eax, eax, 3",
+                    "add byte ptr [rdi + 2], al",
+                ],
+            ),
+            (
+                Op::AddAtOffset {
+                    offset: -1,
+                    delta: 4,
+                },
+                &["add byte ptr [rdi - 1], 4"],
+            ),
+            (Op::JumpIfZero, &["cmp byte ptr [rdi], 0", "je 9"]),
+            (Op::JumpIfNonZero, &["cmp byte ptr [rdi], 0", "jne -9"]),
+        ],
+    );
+}
+
+/// The syscall numbers and error convention differ on macOS.
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[test]
+fn interrupted_reads_are_retried() {
+    check(
+        false,
+        vec![(
+            Op::Input,
+            &[
+                "mov rsi, rdi",
+                "xor edi, edi",
+                "mov edx, 1",
+                "mov eax, 0",
+                "syscall",
+                "cmp rax, -4",
+                // Back to `mov eax, 0`
+                "je -13",
+                // To the failure exit
+                "test rax, rax",
+                "js 16",
+                "test rax, rax",
+                "jne 3",
+                "mov byte ptr [rsi], 0",
+                "mov rdi, rsi",
+            ],
+        )],
+    );
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn bounds_checks_decode_to_their_documented_instructions() {
+    check(
+        true,
+        vec![(
+            Op::MoveRight,
+            &[
+                "inc rdi",
+                "cmp rdi, r9",
+                "jb 5",
+                "cmp rdi, r10",
+                "jb 2",
+                "ud2",
+            ],
+        )],
+    );
+}
+
+#[cfg(target_arch = "aarch64")]
+#[test]
+fn ops_decode_to_their_documented_instructions() {
+    check(
+        false,
+        vec![
+            (
+                Op::Inc,
+                &["ldrb w1, [x0]", "add w1, w1, #1", "strb w1, [x0]"],
+            ),
+            (
+                Op::Dec,
+                &["ldrb w1, [x0]", "sub w1, w1, #1", "strb w1, [x0]"],
+            ),
+            (Op::MoveRight, &["add x0, x0, #1"]),
+            (Op::MoveLeft, &["sub x0, x0, #1"]),
+            (
+                Op::Add(5),
+                &["ldrb w1, [x0]", "add w1, w1, #5", "strb w1, [x0]"],
+            ),
+            (Op::Move(300), &["add x0, x0, #300"]),
+            (Op::Move(-2), &["sub x0, x0, #2"]),
+            (Op::SetZero, &["strb wzr, [x0]"]),
+            (Op::SetConst(7), &["mov w1, #7", "strb w1, [x0]"]),
+            (
+                Op::AddMul {
+                    offset: 2,
+                    factor: 3,
+                },
+                &[
+                    "ldrb w1, [x0]",
+                    "cbz w1, #36",
+                    "nop",
+                    "ldrb w1, [x0]",
+                    "mov w2, #3",
+                    "mul w2, w1, w2",
+                    "add x4, x0, #2",
+                    "ldrb w3, [x4]",
+                    "add w3, w3, w2",
+                    "strb w3, [x4]",
+                ],
+            ),
+            (
+                Op::AddAtOffset {
+                    offset: -1,
+                    delta: 4,
+                },
+                &[
+                    "sub x4, x0, #1",
+                    "ldrb w3, [x4]",
+                    "add w3, w3, #4",
+                    "strb w3, [x4]",
+                ],
+            ),
+            (Op::JumpIfZero, &["ldrb w1, [x0]", "cbz w1, #20", "nop"]),
+            (Op::JumpIfNonZero, &["ldrb w1, [x0]", "cbnz w1, #-4", "nop"]),
+        ],
+    );
+}
+
+#[cfg(target_arch = "aarch64")]
+#[test]
+fn bounds_checks_decode_to_their_documented_instructions() {
+    check(
+        true,
+        vec![(
+            Op::MoveRight,
+            &[
+                "add x0, x0, #1",
+                "cmp x0, x9",
+                "b.lo #12",
+                "cmp x0, x10",
+                "b.lo #8",
+                "brk #0x1",
+            ],
+        )],
+    );
+}
+
+/// The syscall numbers and error convention differ on macOS.
+#[cfg(all(target_arch = "aarch64", target_os = "linux"))]
+#[test]
+fn interrupted_reads_are_retried() {
+    check(
+        false,
+        vec![(
+            Op::Input,
+            &[
+                "mov x3, x0",
+                "mov x0, #0",
+                "mov x1, x3",
+                "mov x2, #1",
+                "mov x8, #63",
+                "svc #0",
+                "cmn x0, #4",
+                // Back to `mov x0, #0`
+                "b.eq #-24",
+                "mov x4, x0",
+                // To the failure exit
+                "cmp x4, #0",
+                "b.lt #28",
+                "nop",
+                "cbnz x0, #8",
+                "strb wzr, [x3]",
+                "mov x0, x3",
+            ],
+        )],
+    );
+}
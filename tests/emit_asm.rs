@@ -0,0 +1,121 @@
+//! Programs translated to AArch64 assembly must use the JIT's instructions.
+
+use brainphoque::{emit_asm, Op};
+
+/// The lines of `emit_asm` for `ops` between the prologue and the epilogue.
+fn body(ops: Vec<Op>) -> Vec<String> {
+    emit_asm(&ops, 30000)
+        .lines()
+        .skip_while(|line| line.trim() != "mov x9, x0")
+        .skip(1)
+        .take_while(|line| line.trim() != "ldrb w0, [x9]")
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+#[test]
+fn pointer_moves_use_shifted_immediates_up_to_24_bits() {
+    let expected: Vec<(isize, &[&str])> = vec![
+        (5, &["add x0, x0, #5"]),
+        (-0xFFF, &["sub x0, x0, #4095"]),
+        (0x1000, &["add x0, x0, #1, lsl #12"]),
+        (0x12345, &["add x0, x0, #18, lsl #12", "add x0, x0, #837"]),
+        (
+            -0xFFFFFF,
+            &["sub x0, x0, #4095, lsl #12", "sub x0, x0, #4095"],
+        ),
+    ];
+
+    for (delta, instructions) in expected {
+        assert_eq!(body(vec![Op::Move(delta)]), instructions, "Move({})", delta);
+    }
+}
+
+#[test]
+fn larger_pointer_moves_go_through_x11() {
+    let expected: Vec<(isize, &[&str])> = vec![
+        (
+            1 << 24,
+            &["movz x11, #0", "movk x11, #256, lsl #16", "add x0, x0, x11"],
+        ),
+        (
+            -0x7FFF_FFFF,
+            &[
+                "movz x11, #65535",
+                "movk x11, #32767, lsl #16",
+                "sub x0, x0, x11",
+            ],
+        ),
+    ];
+
+    for (delta, instructions) in expected {
+        assert_eq!(body(vec![Op::Move(delta)]), instructions, "Move({})", delta);
+    }
+}
+
+#[test]
+fn offsets_are_added_into_x4() {
+    let ops = vec![Op::AddAtOffset {
+        offset: 0x2001,
+        delta: 1,
+    }];
+    assert_eq!(
+        body(ops),
+        [
+            "add x4, x0, #2, lsl #12",
+            "add x4, x4, #1",
+            "ldrb w3, [x4]",
+            "add w3, w3, #1",
+            "strb w3, [x4]",
+        ]
+    );
+
+    let ops = vec![Op::AddAtOffset {
+        offset: 0,
+        delta: 1,
+    }];
+    assert_eq!(body(ops)[0], "add x4, x0, #0");
+}
+
+#[test]
+fn reads_are_retried_after_a_signal_like_the_jit() {
+    assert_eq!(
+        body(vec![Op::Input]),
+        [
+            "mov x3, x0",
+            "2:",
+            "mov x0, #0",
+            "mov x1, x3",
+            "mov x2, #1",
+            "mov x8, #63",
+            "svc #0",
+            "cmn x0, #4",
+            "b.eq 2b",
+            "mov x4, x0",
+            "cmp x4, #0",
+            "b.lt .Lio_failed",
+            "cbnz x0, 1f",
+            "strb wzr, [x3]",
+            "1:",
+            "mov x0, x3",
+        ]
+    );
+}
+
+#[test]
+fn failed_io_exits_with_the_negated_errno() {
+    let asm = emit_asm(&[Op::Output], 30000);
+    assert!(
+        asm.contains("    cmp x4, #0\n    b.lt .Lio_failed\n"),
+        "{}",
+        asm
+    );
+    assert!(
+        asm.contains(".Lio_failed:\n    mov w0, w4\n    ret\n"),
+        "{}",
+        asm
+    );
+
+    // Without I/O there's nothing to fail
+    assert!(!emit_asm(&[Op::Inc], 30000).contains(".Lio_failed"));
+}
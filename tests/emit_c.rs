@@ -0,0 +1,37 @@
+//! Programs translated to C must compile with `cc` and behave like the interpreter.
+
+use std::{env, fs, path::Path, process::Command};
+
+use brainphoque::{emit_c, optimize, parse, DEFAULT_TAPE_SIZE};
+
+#[test]
+fn each_op_becomes_a_statement() {
+    let c = emit_c(&optimize(parse("+.").unwrap()), DEFAULT_TAPE_SIZE);
+
+    assert!(c.contains("static unsigned char tape[30000];"), "{}", c);
+    assert!(c.contains("    *p += 1;\n    putchar(*p);\n"), "{}", c);
+}
+
+#[test]
+fn emitted_hello_world_compiles_and_runs() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_c");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("hello.c");
+    let binary = dir.join("hello");
+
+    let ops = optimize(parse(include_str!("programs/hello.bf")).unwrap());
+    fs::write(&source, emit_c(&ops, DEFAULT_TAPE_SIZE)).unwrap();
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(&source)
+        .arg("-o")
+        .arg(&binary)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, include_bytes!("programs/hello.out"));
+}
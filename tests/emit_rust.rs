@@ -0,0 +1,29 @@
+//! Programs translated to Rust must compile with `rustc` and behave like the interpreter.
+
+use std::{env, fs, path::Path, process::Command};
+
+use brainphoque::{emit_rust, optimize, parse, DEFAULT_TAPE_SIZE};
+
+#[test]
+fn emitted_hello_world_compiles_and_runs() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_rust");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("hello.rs");
+    let binary = dir.join("hello");
+
+    let ops = optimize(parse(include_str!("programs/hello.bf")).unwrap());
+    fs::write(&source, emit_rust(&ops, DEFAULT_TAPE_SIZE)).unwrap();
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let status = Command::new(rustc)
+        .arg(&source)
+        .arg("-o")
+        .arg(&binary)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&binary).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, include_bytes!("programs/hello.out"));
+}
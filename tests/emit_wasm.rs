@@ -0,0 +1,98 @@
+//! Programs translated to WebAssembly text must assemble and behave like the interpreter
+//! when run under `wasmi`.
+
+use brainphoque::{emit_wasm, optimize, parse, DEFAULT_TAPE_SIZE};
+use wasmi::{Caller, Engine, Linker, Module, Store};
+
+/// The program's input, and the output it wrote so far.
+struct Io {
+    input: Vec<u8>,
+    read: usize,
+    output: Vec<u8>,
+}
+
+/// Assemble `src` translated to WebAssembly text and run it on `input`, returning its
+/// output.
+fn run(src: &str, input: &[u8]) -> Vec<u8> {
+    let wat = emit_wasm(&optimize(parse(src).unwrap()), DEFAULT_TAPE_SIZE);
+    let wasm = wat::parse_str(&wat).unwrap();
+
+    let engine = Engine::default();
+    let module = Module::new(&engine, &wasm[..]).unwrap();
+    let mut store = Store::new(
+        &engine,
+        Io {
+            input: input.to_vec(),
+            read: 0,
+            output: vec![],
+        },
+    );
+
+    let mut linker = <Linker<Io>>::new(&engine);
+    linker
+        .func_wrap("env", "read", |mut caller: Caller<'_, Io>| -> i32 {
+            let io = caller.data_mut();
+            match io.input.get(io.read) {
+                Some(&byte) => {
+                    io.read += 1;
+                    byte as i32
+                }
+                None => -1,
+            }
+        })
+        .unwrap();
+    linker
+        .func_wrap("env", "write", |mut caller: Caller<'_, Io>, byte: i32| {
+            caller.data_mut().output.push(byte as u8);
+        })
+        .unwrap();
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    instance
+        .get_typed_func::<(), ()>(&store, "run")
+        .unwrap()
+        .call(&mut store, ())
+        .unwrap();
+
+    store.into_data().output
+}
+
+#[test]
+fn module_imports_io_and_exports_the_tape() {
+    let wat = emit_wasm(&optimize(parse("+.,").unwrap()), DEFAULT_TAPE_SIZE);
+
+    for expected in [
+        "(import \"env\" \"read\" (func $read (result i32)))",
+        "(import \"env\" \"write\" (func $write (param i32)))",
+        "(memory (export \"memory\") 1)",
+        "(func (export \"run\")",
+        "i32.load8_u",
+        "i32.store8",
+    ] {
+        assert!(wat.contains(expected), "{} missing from {}", expected, wat);
+    }
+}
+
+#[test]
+fn emitted_hello_world_runs() {
+    assert_eq!(
+        run(include_str!("programs/hello.bf"), b""),
+        include_bytes!("programs/hello.out")
+    );
+}
+
+#[test]
+fn input_is_echoed_and_stores_zero_at_the_end() {
+    assert_eq!(run(",.,.", b"a"), b"a\0");
+    assert_eq!(
+        run(
+            include_str!("programs/cat.bf"),
+            include_bytes!("programs/cat.in")
+        ),
+        include_bytes!("programs/cat.out")
+    );
+}
@@ -0,0 +1,98 @@
+//! Run the programs in `tests/programs` through every backend and compare their output
+//! byte for byte with the `.out` golden file next to each program.
+//!
+//! A program reads its input from the `.in` file with the same name, if there is one.
+
+use std::{fs, path::PathBuf};
+
+use brainphoque::{optimize, parse, Interpreter, DEFAULT_TAPE_SIZE};
+
+fn fixture(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/programs")
+        .join(name)
+        .with_extension(extension)
+}
+
+fn input(name: &str) -> Vec<u8> {
+    fs::read(fixture(name, "in")).unwrap_or_default()
+}
+
+fn expected(name: &str) -> Vec<u8> {
+    fs::read(fixture(name, "out")).unwrap()
+}
+
+fn interpret(name: &str) -> Vec<u8> {
+    let src = fs::read(fixture(name, "bf")).unwrap();
+    let input = input(name);
+    let mut output = vec![];
+
+    Interpreter::new(
+        optimize(parse(&src).unwrap()),
+        DEFAULT_TAPE_SIZE,
+        &input[..],
+        &mut output,
+    )
+    .run()
+    .unwrap();
+
+    output
+}
+
+/// Compiled code reads and writes the standard file descriptors directly, so the JIT
+/// runs in a child process.
+#[cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+fn jit(name: &str) -> Vec<u8> {
+    use std::process::{Command, Stdio};
+
+    let stdin = match fs::File::open(fixture(name, "in")) {
+        Ok(file) => Stdio::from(file),
+        Err(_) => Stdio::null(),
+    };
+    let output = Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(["--mode", "jit"])
+        .arg(fixture(name, "bf"))
+        .stdin(stdin)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+macro_rules! golden {
+    ($($name:ident),*) => {
+        mod interpreter {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!(super::interpret(stringify!($name)), super::expected(stringify!($name)));
+                }
+            )*
+        }
+
+        #[cfg(all(
+            feature = "jit",
+            any(target_arch = "aarch64", target_arch = "x86_64"),
+            any(target_os = "linux", target_os = "macos")
+        ))]
+        mod jit {
+            $(
+                #[test]
+                fn $name() {
+                    assert_eq!(super::jit(stringify!($name)), super::expected(stringify!($name)));
+                }
+            )*
+        }
+    };
+}
+
+golden!(binary, cat, hello, multiply);
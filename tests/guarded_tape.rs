@@ -0,0 +1,42 @@
+//! A guarded JIT tape must fault instead of letting code run off either end.
+#![cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use std::process::Command;
+
+use brainphoque::GuardedTape;
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(["--mode", "jit", "--guarded-tape"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn tape_is_zeroed_and_rounded_up() {
+    let tape = GuardedTape::new(10).unwrap();
+    assert!(tape.len() >= 10);
+    let cells = unsafe { std::slice::from_raw_parts(tape.as_mut_ptr(), tape.len()) };
+    assert!(cells.iter().all(|&cell| cell == 0));
+}
+
+#[test]
+fn moves_within_the_tape_run_normally() {
+    let output = run(&["-c", "12", "-e", ">>>>>>>>>>>+++."]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+}
+
+#[test]
+fn moving_past_either_end_faults() {
+    for source in ["<+.", "+[>+]."] {
+        let output = run(&["-c", "10", "-e", source]);
+        assert!(!output.status.success(), "{} ran to completion", source);
+        assert!(output.stdout.is_empty(), "{} wrote output", source);
+    }
+}
@@ -0,0 +1,368 @@
+use std::io::{self, Write};
+
+use brainphoque::{
+    optimize, parse, run_to_string, EofPolicy, ExecutionError, Interpreter, Op, OutputMode,
+    PointerMode, DEFAULT_TAPE_SIZE,
+};
+
+/// A writer whose reader has gone away.
+struct ClosedPipe;
+
+impl Write for ClosedPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn write_errors_are_returned() {
+    let ops = optimize(parse("+.").unwrap());
+    let result = Interpreter::new(ops, DEFAULT_TAPE_SIZE, io::empty(), ClosedPipe).run();
+
+    match result {
+        Err(ExecutionError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::BrokenPipe),
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+}
+
+#[test]
+fn run_to_string_captures_output() {
+    assert_eq!(run_to_string(",[.,]", "echo").unwrap(), "echo");
+    assert_eq!(run_to_string("-.", "").unwrap(), "\u{FFFD}");
+    assert!(matches!(
+        run_to_string("[", ""),
+        Err(ExecutionError::Parse(_))
+    ));
+}
+
+#[test]
+fn output_can_be_read_as_text() {
+    let mut interpreter = Interpreter::with_input_bytes(parse(",.,.").unwrap(), "é".as_bytes());
+    interpreter.run().unwrap();
+    assert_eq!(interpreter.output_str(), Ok("é"));
+    assert_eq!(interpreter.output_lossy(), "é");
+
+    // Half of "é"
+    let mut interpreter = Interpreter::with_input_bytes(parse(",.").unwrap(), "é".as_bytes());
+    interpreter.run().unwrap();
+    assert!(interpreter.output_str().is_err());
+    assert_eq!(interpreter.output_lossy(), "\u{FFFD}");
+    assert_eq!(interpreter.into_output(), [0xC3]);
+}
+
+#[test]
+fn inc_and_dec_wrap_at_eight_bits() {
+    for (src, expected) in [("+".repeat(256), 0), ("-".to_string(), 255)] {
+        for ops in [parse(&src).unwrap(), optimize(parse(&src).unwrap())] {
+            let mut interpreter = Interpreter::new(ops, DEFAULT_TAPE_SIZE, io::empty(), io::sink());
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.cell(0), expected, "{} commands", src.len());
+        }
+    }
+}
+
+#[test]
+fn long_output_runs_are_written_whole() {
+    for (mode, unit) in [(OutputMode::Raw, &b"A"[..]), (OutputMode::Decimal, b"65 ")] {
+        let ops = vec![Op::Add(65), Op::OutputRepeat(20_001)];
+        let mut interpreter = Interpreter::with_input_bytes(ops, b"").output_mode(mode);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), unit.repeat(20_001), "{:?}", mode);
+    }
+}
+
+fn run_wide<C: brainphoque::Cell>(src: &str, input: &[u8]) -> Vec<u8> {
+    let mut output = vec![];
+    Interpreter::<_, _, C>::with_cell_type(
+        optimize(parse(src).unwrap()),
+        DEFAULT_TAPE_SIZE,
+        input,
+        &mut output,
+    )
+    .run()
+    .unwrap();
+    output
+}
+
+#[test]
+fn wide_cells_output_their_low_byte() {
+    let src = "+".repeat(0x141) + ".";
+    assert_eq!(run_wide::<u16>(&src, b""), [0x41]);
+    assert_eq!(run_wide::<u32>(&src, b""), [0x41]);
+}
+
+#[test]
+fn wide_cells_zero_extend_input() {
+    // 0x80 + 0x80 only carries into the next byte if the input wasn't sign-extended
+    let src = ",".to_string() + &"+".repeat(0x80) + "[.[-]]";
+    assert_eq!(run_wide::<u16>(&src, &[0x80]), [0x00]);
+    assert_eq!(run_wide::<u32>(&src, &[0x80]), [0x00]);
+}
+
+#[test]
+fn restoring_a_snapshot_rewinds_the_program() {
+    let ops = parse("+++>++<[->+<]>.").unwrap();
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(ops, DEFAULT_TAPE_SIZE, io::empty(), &mut output);
+
+    for _ in 0..6 {
+        interpreter.step().unwrap();
+    }
+    let state = interpreter.snapshot();
+    assert_eq!((state.ip, state.dp, &state.tape[..]), (6, 1, &[3, 2][..]));
+
+    while interpreter.step().unwrap() {}
+    assert_eq!(interpreter.cell(1), 5);
+
+    interpreter.restore(&state);
+    assert_eq!(interpreter.snapshot(), state);
+    while interpreter.step().unwrap() {}
+    interpreter.flush().unwrap();
+    drop(interpreter);
+    assert_eq!(output, [5, 5]);
+}
+
+#[test]
+fn loops_are_profiled_hottest_first() {
+    // Outer loop at op 2 runs twice; the inner loop at op 6 runs twice per outer iteration
+    let ops = parse("++[>++[>+<-]<-]").unwrap();
+    let stats = Interpreter::new(ops, DEFAULT_TAPE_SIZE, io::empty(), io::sink())
+        .run()
+        .unwrap();
+
+    let loops: Vec<_> = stats
+        .loops
+        .iter()
+        .map(|profile| (profile.start, profile.end, profile.iterations))
+        .collect();
+    assert_eq!(loops, [(2, 14, 2), (6, 11, 4)]);
+    assert!(stats.loops[0].ops_executed > stats.loops[1].ops_executed);
+    assert_eq!(stats.loops[0].ops_executed, stats.ops_executed - 2);
+}
+
+#[test]
+fn hot_loops_are_ranked_by_iterations() {
+    // The first loop runs 3 long iterations, the second 5 short ones
+    let ops = parse("+++[->>>>>>>>+<<<<<<<<]+++++[-]").unwrap();
+    let stats = Interpreter::new(ops, DEFAULT_TAPE_SIZE, io::empty(), io::sink())
+        .run()
+        .unwrap();
+
+    assert_eq!(stats.loops[0].start, 3);
+    let hot: Vec<_> = stats
+        .hot_loops()
+        .iter()
+        .map(|profile| (profile.start, profile.iterations))
+        .collect();
+    assert_eq!(hot, [(28, 5), (3, 3)]);
+    assert_eq!(
+        stats.loop_iterations().into_iter().collect::<Vec<_>>(),
+        [(3, 3), (28, 5)]
+    );
+}
+
+/// Run `src` at every optimization on a wrapping tape of `len` cells, returning the
+/// final data pointer and cells.
+fn run_wrapping(src: &str, len: usize) -> (usize, Vec<u8>) {
+    let results: Vec<_> = [parse(src).unwrap(), optimize(parse(src).unwrap())]
+        .into_iter()
+        .map(|ops| {
+            let mut interpreter =
+                Interpreter::new(ops, len, io::empty(), io::sink()).pointer_mode(PointerMode::Wrap);
+            interpreter.run().unwrap();
+            (
+                interpreter.dp(),
+                (0..len).map(|i| interpreter.cell(i)).collect(),
+            )
+        })
+        .collect();
+
+    assert_eq!(results[0], results[1], "{} differs once optimized", src);
+    results[0].clone()
+}
+
+#[test]
+fn wrapping_pointer_goes_round_the_tape() {
+    assert_eq!(run_wrapping("<+", 4), (3, vec![0, 0, 0, 1]));
+    assert_eq!(run_wrapping(">>>>+", 4), (0, vec![1, 0, 0, 0]));
+    assert_eq!(run_wrapping("<<<<<<+", 4), (2, vec![0, 0, 1, 0]));
+    assert_eq!(run_wrapping("+<<+>>", 4), (0, vec![1, 0, 1, 0]));
+}
+
+#[test]
+fn wrapping_scans_continue_from_the_other_end() {
+    assert_eq!(run_wrapping(">+>+>+[>]", 4), (0, vec![0, 1, 1, 1]));
+    assert_eq!(run_wrapping("+>+<<<[<]", 4), (2, vec![1, 1, 0, 0]));
+    assert_eq!(run_wrapping("+[<]", 4), (3, vec![1, 0, 0, 0]));
+}
+
+#[test]
+fn wrapping_scan_without_a_zero_cell_never_finishes() {
+    let ops = optimize(parse("+>+<[>]").unwrap());
+    let result = Interpreter::new(ops, 2, io::empty(), io::sink())
+        .pointer_mode(PointerMode::Wrap)
+        .max_steps(Some(1000))
+        .run();
+    assert!(matches!(
+        result,
+        Err(ExecutionError::StepLimitExceeded { .. })
+    ));
+}
+
+#[test]
+fn moving_past_the_last_cell_is_a_tape_overflow() {
+    for src in [
+        "+>>>+",
+        "+>>>>>",
+        "+[>+]",
+        "+>+>+<<[->>>+<<<]",
+        "+>+>+<<[>]",
+    ] {
+        for ops in [parse(src).unwrap(), optimize(parse(src).unwrap())] {
+            let result = Interpreter::new(ops, 3, io::empty(), io::sink()).run();
+            assert!(
+                matches!(result, Err(ExecutionError::TapeOverflow { dp }) if dp >= 3),
+                "{} gave {:?}",
+                src,
+                result
+            );
+        }
+    }
+}
+
+#[test]
+fn moving_left_of_the_first_cell_is_a_pointer_underflow() {
+    for (src, ip) in [("<", 0), ("+><<", 3), ("+[<]", 2), ("+>+[-<<+>>]", 6)] {
+        let result = Interpreter::new(parse(src).unwrap(), 3, io::empty(), io::sink()).run();
+        assert!(
+            matches!(result, Err(ExecutionError::PointerUnderflow { ip: at }) if at == ip),
+            "{} gave {:?}",
+            src,
+            result
+        );
+
+        let result =
+            Interpreter::new(optimize(parse(src).unwrap()), 3, io::empty(), io::sink()).run();
+        assert!(
+            matches!(result, Err(ExecutionError::PointerUnderflow { .. })),
+            "{} gave {:?} once optimized",
+            src,
+            result
+        );
+    }
+
+    let mut interpreter = Interpreter::new(parse("+<").unwrap(), 3, io::empty(), io::sink());
+    assert!(matches!(
+        interpreter.run(),
+        Err(ExecutionError::PointerUnderflow { ip: 1 })
+    ));
+    assert_eq!(interpreter.ip(), 1);
+}
+
+#[test]
+fn start_offset_cells_count_towards_the_tape_size() {
+    let mut interpreter =
+        Interpreter::new(parse("<+>>+").unwrap(), 3, io::empty(), io::sink()).start_offset(1);
+    interpreter.run().unwrap();
+    assert_eq!(interpreter.tape(), [1, 0, 1]);
+
+    let result = Interpreter::new(parse(">>").unwrap(), 3, io::empty(), io::sink())
+        .start_offset(1)
+        .run();
+    assert!(matches!(
+        result,
+        Err(ExecutionError::TapeOverflow { dp: 3 })
+    ));
+}
+
+#[test]
+fn empty_loop_on_a_nonzero_cell_is_an_error() {
+    assert_eq!(run_to_string(">[]+.", "").unwrap(), "\u{1}");
+
+    let mut interpreter = Interpreter::new(parse("+[]").unwrap(), 3, io::empty(), io::sink());
+    assert!(matches!(
+        interpreter.run(),
+        Err(ExecutionError::InfiniteLoop)
+    ));
+    assert_eq!(interpreter.ip(), 1);
+}
+
+#[test]
+fn input_bytes_run_out_into_the_eof_policy() {
+    let mut interpreter = Interpreter::with_input_bytes(parse(",>,>,.").unwrap(), b"ab")
+        .eof_policy(EofPolicy::NegativeOne);
+    interpreter.run().unwrap();
+
+    assert_eq!([0, 1, 2].map(|i| interpreter.cell(i)), [b'a', b'b', 255]);
+    assert_eq!(interpreter.output(), [255]);
+}
+
+#[test]
+fn long_moves_respect_the_tape_size_in_one_step() {
+    for (src, mode) in [(">", PointerMode::Strict), ("<", PointerMode::Grow)] {
+        let src = src.repeat(2000) + "+";
+        let ops = || optimize(parse(&src).unwrap());
+        assert_eq!(ops().len(), 2, "{:?}", ops());
+
+        let result = Interpreter::new(ops(), 1000, io::empty(), io::sink())
+            .pointer_mode(mode)
+            .run();
+        assert!(
+            matches!(result, Err(ExecutionError::TapeOverflow { .. })),
+            "{:?} gave {:?}",
+            mode,
+            result
+        );
+
+        let mut interpreter =
+            Interpreter::new(ops(), 5000, io::empty(), io::sink()).pointer_mode(mode);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.tape().len(), 2001, "{:?}", mode);
+        assert_eq!(interpreter.cell(interpreter.dp()), 1, "{:?}", mode);
+    }
+}
+
+#[test]
+fn hook_sees_every_op_before_it_runs() {
+    use std::{cell::RefCell, rc::Rc};
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    let log = Rc::clone(&seen);
+    let mut interpreter = Interpreter::with_input_bytes(parse("+>++[-]").unwrap(), b"")
+        .on_op(move |ip, dp, op, tape| log.borrow_mut().push((ip, dp, op.name(), tape.to_vec())));
+    let stats = interpreter.run().unwrap();
+
+    let seen = seen.borrow();
+    assert_eq!(seen.len() as u64, stats.ops_executed);
+    assert_eq!(seen[0], (0, 0, "Inc", vec![0]));
+    assert_eq!(seen[2], (2, 1, "Inc", vec![1, 0]));
+    assert_eq!(seen.last().unwrap(), &(6, 1, "JumpIfNonZero", vec![1, 0]));
+}
+
+#[test]
+fn output_events_replace_the_written_bytes() {
+    use std::sync::mpsc;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut output = vec![];
+    Interpreter::new(
+        optimize(parse("+++.>+..").unwrap()),
+        DEFAULT_TAPE_SIZE,
+        io::empty(),
+        &mut output,
+    )
+    .on_output(move |event| sender.send(event).unwrap())
+    .run()
+    .unwrap();
+
+    let events: Vec<_> = receiver
+        .iter()
+        .map(|event| (event.byte, event.ip, event.dp))
+        .collect();
+    assert_eq!(events, [(3, 1, 0), (1, 4, 1), (1, 4, 1)]);
+    assert!(output.is_empty());
+}
@@ -0,0 +1,241 @@
+#![cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use brainphoque::{optimize, parse, run_to_string, CompileError, JitCompiler, Op, Tape};
+
+fn call(src: &str) -> i32 {
+    let mut tape = [0u8; 16];
+    let compiled = JitCompiler::new(optimize(parse(src).unwrap()))
+        .compile()
+        .unwrap();
+    unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) }
+}
+
+#[test]
+fn returns_the_first_cell() {
+    assert_eq!(call("+++"), 3);
+    assert_eq!(call("+++>++++"), 3);
+    assert_eq!(call("-"), 255);
+    assert_eq!(call("++[-]"), 0);
+}
+
+#[test]
+fn loops_run_until_the_cell_is_zero() {
+    // Unoptimized, so the loop compiles to real branches rather than a multiply
+    let compiled = JitCompiler::new(parse("+++[>+<-]").unwrap())
+        .compile()
+        .unwrap();
+    let mut tape = [0u8; 4];
+    unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) };
+    assert_eq!(tape, [0, 3, 0, 0]);
+}
+
+#[test]
+fn inc_and_dec_wrap_at_eight_bits() {
+    for (src, expected) in [("+".repeat(256), 0), ("-".to_string(), 255)] {
+        for ops in [parse(&src).unwrap(), optimize(parse(&src).unwrap())] {
+            let mut tape = [0u8; 1];
+            let compiled = JitCompiler::new(ops).compile().unwrap();
+            unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) };
+            assert_eq!(tape, [expected], "{} commands", src.len());
+        }
+    }
+}
+
+#[test]
+fn compiled_program_runs_against_many_tapes() {
+    // Unoptimized, since the optimizer drops a loop at the start of a program on the
+    // assumption that the tape starts zeroed
+    let compiled = JitCompiler::new(parse("[->+<]>+++").unwrap())
+        .compile()
+        .unwrap();
+
+    let mut first = [2u8, 0, 0];
+    let mut second = [40u8, 0, 0];
+    unsafe {
+        compiled.call(first.as_mut_ptr(), first.len());
+        compiled.call(second.as_mut_ptr(), second.len());
+    }
+
+    assert_eq!(first, [0, 5, 0]);
+    assert_eq!(second, [0, 43, 0]);
+}
+
+#[test]
+fn compiled_programs_release_their_memory_when_dropped() {
+    // Each program maps its own pages, so leaking them would run into the limit on
+    // mappings long before the loop ends
+    for n in 0..100_000 {
+        let compiled = JitCompiler::new(vec![Op::Add((n % 128) as i8)])
+            .compile()
+            .unwrap();
+        let mut tape = [0u8; 1];
+        let first = unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) };
+        assert_eq!(first, n % 128);
+    }
+}
+
+#[test]
+fn codegen_emits_the_documented_instructions() {
+    let compiler = JitCompiler::new(parse("+").unwrap());
+    let code = compiler.codegen().unwrap();
+
+    // inc byte [rdi]
+    #[cfg(target_arch = "x86_64")]
+    let inc: &[u8] = &[0xFE, 0x07];
+    // ldrb w1, [x0]; add w1, w1, #1; strb w1, [x0]
+    #[cfg(target_arch = "aarch64")]
+    let inc: &[u8] = &[
+        0x01, 0x00, 0x40, 0x39, 0x21, 0x04, 0x00, 0x11, 0x01, 0x00, 0x00, 0x39,
+    ];
+
+    assert!(
+        code.windows(inc.len()).any(|window| window == inc),
+        "{:02x?}",
+        code
+    );
+    assert_eq!(code, compiler.codegen().unwrap());
+}
+
+#[test]
+fn loops_around_megabytes_of_code() {
+    // Unoptimized, each `+-` pair is several instructions, putting the loop's branch
+    // targets past the reach of a single conditional branch on AArch64
+    let body = "+-".repeat(100_000);
+    for (src, expected) in [
+        (format!("+[{}-]+++", body), 3),
+        (format!("[{}]++", body), 2),
+    ] {
+        let mut tape = [0u8; 4];
+        let compiled = JitCompiler::new(parse(&src).unwrap()).compile().unwrap();
+        assert_eq!(
+            unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) },
+            expected
+        );
+    }
+}
+
+#[test]
+fn programs_out_of_the_jits_reach_are_errors() {
+    // x86-64 addresses cells with 32-bit displacements
+    #[cfg(target_arch = "x86_64")]
+    let (ops, expected) = (vec![Op::Move(1 << 31)], CompileError::OffsetOutOfRange);
+    // Hundreds of megabytes of output code put the I/O error exit past a `B`'s ±128MB
+    #[cfg(target_arch = "aarch64")]
+    let (ops, expected) = (
+        vec![Op::OutputRepeat(3 << 31)],
+        CompileError::BranchOutOfRange,
+    );
+    let err = JitCompiler::new(ops).compile().err().unwrap();
+    assert_eq!(err.to_string(), expected.to_string());
+}
+
+#[test]
+fn writes_are_observable_through_the_tape() {
+    let compiled = JitCompiler::new(optimize(parse("++>+++>[-]+<<").unwrap()))
+        .compile()
+        .unwrap();
+    let mut tape = Tape::new(8);
+    unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) };
+    assert_eq!(tape.cells(), [2, 3, 1, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn object_file_exports_the_program_as_bf_main() {
+    use object::{Object, ObjectSection, ObjectSymbol, SectionKind};
+
+    let compiler = JitCompiler::new(optimize(parse("++[>+++<-]>.").unwrap()));
+    let bytes = compiler.object().unwrap();
+    let file = object::File::parse(&bytes[..]).unwrap();
+
+    let text = file
+        .sections()
+        .find(|section| section.kind() == SectionKind::Text)
+        .unwrap();
+    assert_eq!(text.size(), compiler.codegen().unwrap().len() as u64);
+
+    // Mach-O symbols carry a leading underscore
+    let symbol = file
+        .symbols()
+        .find(|symbol| symbol.name().unwrap().trim_start_matches('_') == "bf_main")
+        .unwrap();
+    assert!(symbol.is_global());
+    assert_eq!(symbol.section_index(), Some(text.index()));
+    assert_eq!(symbol.address(), text.address());
+}
+
+#[test]
+fn long_moves_take_a_single_add() {
+    let code = JitCompiler::new(vec![Op::Move(5000)]).codegen().unwrap();
+
+    // add rdi, 5000
+    #[cfg(target_arch = "x86_64")]
+    let add: &[u8] = &[0x48, 0x81, 0xC7, 0x88, 0x13, 0x00, 0x00];
+    // add x0, x0, #1, lsl #12; add x0, x0, #904
+    #[cfg(target_arch = "aarch64")]
+    let add: &[u8] = &[0x00, 0x04, 0x40, 0x91, 0x00, 0x20, 0x0E, 0x91];
+
+    assert!(
+        code.windows(add.len()).any(|window| window == add),
+        "{:02x?}",
+        code
+    );
+
+    // Past the shifted immediate's range, AArch64 adds a register instead
+    for distance in [5000, 4096, 20_000_000] {
+        let compiled = JitCompiler::new(vec![
+            Op::Move(distance),
+            Op::Inc,
+            Op::Move(-distance),
+            Op::Add(2),
+        ])
+        .compile()
+        .unwrap();
+        let mut tape = Tape::new(distance as usize + 1);
+        unsafe { compiled.call(tape.as_mut_ptr(), tape.len()) };
+        assert_eq!(
+            (tape.cells()[0], tape.cells()[distance as usize]),
+            (2, 1),
+            "{}",
+            distance
+        );
+    }
+}
+
+#[test]
+fn long_runs_of_adds_wrap_like_the_interpreter() {
+    let src = "+".repeat(300);
+    let ops = optimize(parse(&src).unwrap());
+    assert!(ops.iter().filter(|op| matches!(op, Op::Add(_))).count() > 1);
+
+    let compiler = JitCompiler::new(ops);
+    let code = compiler.codegen().unwrap();
+    // add byte [rdi], 44
+    #[cfg(target_arch = "x86_64")]
+    let add: &[u8] = &[0x80, 0x07, 0x2C];
+    // add w1, w1, #44
+    #[cfg(target_arch = "aarch64")]
+    let add: &[u8] = &[0x21, 0xB0, 0x00, 0x11];
+    assert_eq!(
+        code.windows(add.len())
+            .filter(|window| window == &add)
+            .count(),
+        1
+    );
+
+    let mut tape = Tape::new(1);
+    let jit = unsafe {
+        compiler
+            .compile()
+            .unwrap()
+            .call(tape.as_mut_ptr(), tape.len())
+    };
+    assert_eq!(
+        run_to_string(&(src + "."), "").unwrap(),
+        char::from(jit as u8).to_string()
+    );
+    assert_eq!(jit, 44);
+}
@@ -0,0 +1,176 @@
+//! Every optimization level must leave a program's behavior unchanged.
+
+use std::fs;
+
+use brainphoque::{
+    optimize_with_level, optimize_with_positions, parse, parse_with_positions, Interpreter, Op,
+    OptLevel, DEFAULT_MAX_LOOP_DEPTH, DEFAULT_TAPE_SIZE,
+};
+
+fn run(src: &str, level: OptLevel) -> Vec<u8> {
+    let mut output = vec![];
+    Interpreter::new(
+        optimize_with_level(parse(src).unwrap(), level),
+        DEFAULT_TAPE_SIZE,
+        &b""[..],
+        &mut output,
+    )
+    .run()
+    .unwrap();
+
+    output
+}
+
+#[test]
+fn levels_produce_identical_output() {
+    for name in ["hello", "multiply"] {
+        let path = format!("{}/tests/programs/{}.bf", env!("CARGO_MANIFEST_DIR"), name);
+        let src = fs::read_to_string(path).unwrap();
+
+        let unoptimized = run(&src, OptLevel::O0);
+        assert_eq!(run(&src, OptLevel::O1), unoptimized, "{} at -O1", name);
+        assert_eq!(run(&src, OptLevel::O2), unoptimized, "{} at -O2", name);
+    }
+}
+
+#[test]
+fn optimized_ops_keep_the_position_of_their_first_command() {
+    let (ops, positions) =
+        parse_with_positions(",++ [-] >>\n[->+<] .", false, false, DEFAULT_MAX_LOOP_DEPTH).unwrap();
+    let (ops, positions) = optimize_with_positions(ops, positions, OptLevel::O2);
+
+    let names: Vec<_> = ops.iter().map(|op| op.name()).collect();
+    assert_eq!(
+        names,
+        ["Input", "Add", "SetZero", "Move", "AddMul", "SetZero", "Output"]
+    );
+    assert_eq!(positions, [0, 1, 4, 8, 11, 11, 18]);
+}
+
+#[test]
+fn offset_blocks_touch_the_same_cells() {
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let ops = optimize_with_level(parse(">+>+>+<<<").unwrap(), level);
+        let mut interpreter = Interpreter::new(ops, DEFAULT_TAPE_SIZE, &b""[..], vec![]);
+        interpreter.run().unwrap();
+
+        let cells: Vec<u8> = (0..5).map(|i| interpreter.cell(i)).collect();
+        assert_eq!(cells, [0, 1, 1, 1, 0], "at {:?}", level);
+        assert_eq!(interpreter.dp(), 0, "at {:?}", level);
+    }
+}
+
+#[test]
+fn scan_loops_land_on_the_same_cell() {
+    let programs = [
+        "+>+>+>>+<<<<[>]",
+        ">+>+>+>+[<]",
+        ">>+>+>+<<[>]",
+        "+>+>>+>+>+[<]>[>]>[>]",
+        "+++[>]",
+    ];
+
+    for src in programs {
+        let dps: Vec<usize> = [OptLevel::O0, OptLevel::O2]
+            .into_iter()
+            .map(|level| {
+                let ops = optimize_with_level(parse(src).unwrap(), level);
+                let mut interpreter = Interpreter::new(ops, DEFAULT_TAPE_SIZE, &b""[..], vec![]);
+                interpreter.run().unwrap();
+                interpreter.dp()
+            })
+            .collect();
+
+        assert_eq!(dps[0], dps[1], "{}", src);
+    }
+}
+
+#[test]
+fn clear_loops_run_as_a_single_op() {
+    let ops = optimize_with_level(parse("+++++[-]").unwrap(), OptLevel::O1);
+    assert_eq!(format!("{:?}", ops), "[Add(5), SetZero]");
+
+    let mut interpreter = Interpreter::new(ops, DEFAULT_TAPE_SIZE, &b""[..], vec![]);
+    let stats = interpreter.run().unwrap();
+    assert_eq!(interpreter.cell(0), 0);
+    assert_eq!(stats.op_counts["SetZero"], 1);
+    assert_eq!(stats.ops_executed, 2);
+}
+
+#[test]
+fn clear_then_add_sets_a_constant_in_one_op() {
+    let ops = optimize_with_level(parse(",[-]+++++").unwrap(), OptLevel::O2);
+    assert_eq!(format!("{:?}", ops), "[Input, SetConst(5)]");
+
+    for (src, expected) in [("+[-]+++++", 5), ("++[-]--", 254)] {
+        let ops = optimize_with_level(parse(src).unwrap(), OptLevel::O2);
+        let mut interpreter = Interpreter::new(ops, DEFAULT_TAPE_SIZE, &b""[..], vec![]);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.cell(0), expected, "{}", src);
+    }
+}
+
+#[test]
+fn loops_that_start_on_a_zero_cell_are_removed() {
+    let ops = optimize_with_level(parse("[->+<]+.").unwrap(), OptLevel::O1);
+    assert_eq!(format!("{:?}", ops), "[Add(1), Output]");
+
+    // A loop straight after another starts on the zero cell the first one left behind
+    let ops = optimize_with_level(parse("+[-][[.]>]+").unwrap(), OptLevel::O1);
+    assert_eq!(format!("{:?}", ops), "[Add(1), SetZero, Add(1)]");
+}
+
+#[test]
+fn only_odd_steps_are_clear_loops() {
+    for (src, clears) in [
+        ("[+]", true),
+        ("[-]", true),
+        ("[---]", true),
+        ("[--]", false),
+    ] {
+        let ops = optimize_with_level(parse(format!(",{}", src)).unwrap(), OptLevel::O1);
+        assert_eq!(
+            matches!(ops[..], [Op::Input, Op::SetZero]),
+            clears,
+            "{} gave {:?}",
+            src,
+            ops
+        );
+    }
+
+    // 255 - 3 * 85 = 0, and 1 - 3 * 171 = -512, which wraps to 0
+    for input in [[255], [1]] {
+        for level in [OptLevel::O0, OptLevel::O1] {
+            let ops = optimize_with_level(parse(",[---].").unwrap(), level);
+            let mut interpreter = Interpreter::with_input_bytes(ops, &input);
+            interpreter.run().unwrap();
+            assert_eq!(interpreter.output(), [0], "{:?} at {:?}", input, level);
+        }
+    }
+}
+
+#[test]
+fn start_of_the_program_is_evaluated_on_the_zeroed_tape() {
+    for (src, expected) in [
+        ("[-]+.", "[Add(1), Output]"),
+        (">[-]<+.", "[Add(1), Output]"),
+        (
+            "++[->+++<]>-.",
+            "[AddAtOffset { offset: 1, delta: 5 }, Move(1), Output]",
+        ),
+        (
+            "+.[-]>[>]+.",
+            "[Add(1), Output, Add(-1), AddAtOffset { offset: 1, delta: 1 }, Move(1), Output]",
+        ),
+        // Evaluation stops at input
+        ("+>,[-]", "[Add(1), Move(1), Input, SetZero]"),
+    ] {
+        let ops = optimize_with_level(parse(src).unwrap(), OptLevel::O2);
+        assert_eq!(format!("{:?}", ops), expected, "{}", src);
+        assert_eq!(run(src, OptLevel::O2), run(src, OptLevel::O0), "{}", src);
+    }
+
+    // and where the pointer would leave the tape
+    let ops = optimize_with_level(parse("+<-").unwrap(), OptLevel::O2);
+    assert_eq!(format!("{:?}", ops), "[Add(1), Move(-1), Add(-1)]");
+}
@@ -0,0 +1,167 @@
+use brainphoque::{
+    lower, nest, optimize, parse, parse_tree, parse_with_positions, Op, ParseError,
+    DEFAULT_MAX_LOOP_DEPTH,
+};
+use proptest::prelude::*;
+
+#[test]
+fn unmatched_brackets_report_their_position() {
+    match parse("+[-]\n+]]") {
+        Err(ParseError::UnmatchedClose { pos, location }) => {
+            assert_eq!(pos, 6);
+            assert_eq!((location.line, location.column), (2, 2));
+        }
+        other => panic!("expected an unmatched ']', got {:?}", other),
+    }
+
+    match parse("[[-]") {
+        Err(ParseError::UnmatchedOpen { pos, .. }) => assert_eq!(pos, 0),
+        other => panic!("expected an unmatched '[', got {:?}", other),
+    }
+}
+
+#[test]
+fn nesting_past_the_limit_is_rejected() {
+    assert!(parse_with_positions("[[[-]]]", false, false, 3).is_ok());
+
+    match parse_with_positions("[[[[-]]]]", false, false, 3) {
+        Err(ParseError::NestingTooDeep { pos, limit, .. }) => assert_eq!((pos, limit), (3, 3)),
+        other => panic!("expected nesting to be too deep, got {:?}", other),
+    }
+
+    let deep = "[".repeat(DEFAULT_MAX_LOOP_DEPTH + 1) + &"]".repeat(DEFAULT_MAX_LOOP_DEPTH + 1);
+    assert!(matches!(
+        parse(&deep),
+        Err(ParseError::NestingTooDeep { .. })
+    ));
+}
+
+#[test]
+fn strict_mode_rejects_stray_bytes() {
+    assert_eq!(parse("+?+").unwrap().len(), 2);
+    assert!(parse_with_positions("+ +\t\r\n+", false, true, DEFAULT_MAX_LOOP_DEPTH).is_ok());
+
+    match parse_with_positions("+?+", false, true, DEFAULT_MAX_LOOP_DEPTH) {
+        Err(
+            err @ ParseError::StrayByte {
+                pos: 1, byte: b'?', ..
+            },
+        ) => {
+            assert!(err
+                .to_string()
+                .starts_with("stray character '?' at line 1, column 2"));
+        }
+        other => panic!("expected a stray '?', got {:?}", other),
+    }
+
+    // `#` is only a command with extensions
+    assert!(parse_with_positions("+#", true, true, DEFAULT_MAX_LOOP_DEPTH).is_ok());
+    assert!(parse_with_positions("+#", false, true, DEFAULT_MAX_LOOP_DEPTH).is_err());
+}
+
+#[test]
+fn tree_nests_loop_bodies() {
+    assert_eq!(
+        format!("{:?}", parse_tree("+[-[>]],").unwrap()),
+        "[Inc, Loop([Dec, Loop([MoveRight])]), Input]"
+    );
+
+    // As deep as the parser allows, without running out of stack
+    let deep = "[".repeat(DEFAULT_MAX_LOOP_DEPTH) + &"]".repeat(DEFAULT_MAX_LOOP_DEPTH);
+    assert_eq!(lower(parse_tree(&deep).unwrap()).len(), deep.len());
+    assert!(optimize(parse(&deep).unwrap()).is_empty());
+}
+
+#[test]
+fn far_deeper_nesting_than_the_default_limit_fits_on_the_stack() {
+    const DEPTH: usize = 200_000;
+    let deep = "+".to_string() + &"[".repeat(DEPTH) + "-" + &"]".repeat(DEPTH);
+
+    let (ops, _) = parse_with_positions(&deep, false, false, DEPTH).unwrap();
+    assert_eq!(ops.len(), deep.len());
+    let tree = nest(ops);
+    assert_eq!(tree.len(), 2);
+    assert_eq!(lower(tree).len(), deep.len());
+
+    // The innermost `[-]` becomes one SetZero
+    let (ops, _) = parse_with_positions(&deep, false, false, DEPTH).unwrap();
+    assert_eq!(optimize(ops).len(), 2 * DEPTH);
+    drop(nest(
+        parse_with_positions(&deep, false, false, DEPTH).unwrap().0,
+    ));
+}
+
+/// Check that parsing `src` either succeeds with balanced loops whose positions point at
+/// the commands they came from, or fails pointing at a bracket.
+fn check_parse(src: &str, max_depth: usize) {
+    match parse_with_positions(src, true, false, max_depth) {
+        Ok((ops, positions)) => {
+            assert_eq!(ops.len(), positions.len());
+
+            let mut depth = 0usize;
+            for (op, &pos) in ops.iter().zip(&positions) {
+                let command = match op {
+                    Op::JumpIfZero => {
+                        depth += 1;
+                        assert!(depth <= max_depth);
+                        '['
+                    }
+                    Op::JumpIfNonZero => {
+                        depth = depth.checked_sub(1).expect("unmatched ']' accepted");
+                        ']'
+                    }
+                    Op::Inc => '+',
+                    Op::Dec => '-',
+                    Op::MoveLeft => '<',
+                    Op::MoveRight => '>',
+                    Op::Output => '.',
+                    Op::Input => ',',
+                    Op::DebugDump => '#',
+                    op => panic!("parser produced {:?}", op),
+                };
+                assert_eq!(src[pos..].chars().next(), Some(command));
+            }
+            assert_eq!(depth, 0, "unmatched '[' accepted");
+        }
+        Err(ParseError::UnmatchedOpen { pos, .. } | ParseError::NestingTooDeep { pos, .. }) => {
+            assert_eq!(src[pos..].chars().next(), Some('['))
+        }
+        Err(ParseError::UnmatchedClose { pos, .. }) => {
+            assert_eq!(src[pos..].chars().next(), Some(']'))
+        }
+        Err(err @ ParseError::StrayByte { .. }) => panic!("{} outside strict mode", err),
+    }
+}
+
+proptest! {
+    #[test]
+    fn parser_never_panics_on_arbitrary_text(src in any::<String>()) {
+        check_parse(&src, DEFAULT_MAX_LOOP_DEPTH);
+    }
+
+    #[test]
+    fn parser_never_panics_on_arbitrary_bytes(src in any::<Vec<u8>>()) {
+        // Commands are ASCII, so replacing invalid sequences mustn't change the program
+        let text = String::from_utf8_lossy(&src);
+        let ops = |src: &[u8]| parse(src).map(|ops| format!("{:?}", ops)).ok();
+        prop_assert_eq!(ops(&src), ops(text.as_bytes()));
+    }
+
+    #[test]
+    fn tree_round_trips_to_the_flat_ops(src in "[\\[\\]+<>.,]{0,300}") {
+        if let Ok(ops) = parse(&src) {
+            let flat = format!("{:?}", ops);
+            let tree = format!("{:?}", parse_tree(&src).unwrap());
+            prop_assert_eq!(format!("{:?}", lower(parse_tree(&src).unwrap())), flat);
+            prop_assert_eq!(format!("{:?}", nest(ops)), tree);
+        }
+    }
+
+    #[test]
+    fn parser_handles_bracket_heavy_text(
+        src in "[\\[\\]+<>.,#é\n ]{0,300}",
+        max_depth in 0usize..8,
+    ) {
+        check_parse(&src, max_depth);
+    }
+}
@@ -0,0 +1,4 @@
+Prints ok with comments that aren't valid UTF8 
+++++++++++([>+++++++++++<-]>+.  o
+----. k 
+[-]++++++++++.  newline
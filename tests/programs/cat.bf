@@ -0,0 +1,2 @@
+Copy input to output until a zero byte
+,[.,]
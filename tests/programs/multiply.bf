@@ -0,0 +1,4 @@
+Multiply 6 by 7 and print the product as a byte (42 is an asterisk)
+++++++[>+++++++<-]>.
+Then print a newline
+[-]++++++++++.
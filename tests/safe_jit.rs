@@ -0,0 +1,32 @@
+//! Bounds-checked JIT code must trap instead of running off the tape.
+#![cfg(all(
+    feature = "jit",
+    any(target_arch = "aarch64", target_arch = "x86_64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use std::process::Command;
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_brainphoque"))
+        .args(["--mode", "jit", "--safe-jit"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn moves_within_the_tape_run_normally() {
+    let output = run(&["-c", "12", "-e", ">>>>>>>>>>>+++."]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+}
+
+#[test]
+fn moving_past_either_end_traps() {
+    for source in [">>>>>>>>>>>+.", "<+.", "+[->>>>>>>>>>>+<<<<<<<<<<<]"] {
+        let output = run(&["-c", "10", "-e", source]);
+        assert!(!output.status.success(), "{} ran to completion", source);
+        assert!(output.stdout.is_empty(), "{} wrote output", source);
+    }
+}